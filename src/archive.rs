@@ -0,0 +1,204 @@
+use crate::config::Config;
+use crate::{relative, Collection, Image, Item, Summary};
+use anyhow::{anyhow, Result};
+use chrono::Datelike;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, write};
+use std::path::{Path, PathBuf};
+
+/// Items grouped by year and month of their date.
+type Timeline<'a> = BTreeMap<i32, BTreeMap<u32, Vec<&'a Item>>>;
+
+/// Context of an archive page.
+#[derive(Serialize)]
+struct Page<'a> {
+    /// Year of the page, `None` on the overview of all years.
+    year: Option<i32>,
+    /// Month of the page zero-padded like its directory, only set on month pages.
+    month: Option<String>,
+    /// Years or months listed on the overview and year pages.
+    periods: Vec<Summary>,
+    /// Images of a month page in chronological order.
+    images: Vec<Image<'a>>,
+}
+
+/// Group all dated items of `collection` and its children by year and month.
+fn collect(collection: &Collection) -> Timeline<'_> {
+    let mut items = collection.listed();
+    items.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.to.cmp(&b.to)));
+
+    let mut timeline = Timeline::new();
+
+    for item in items {
+        if let Some(date) = item.date {
+            timeline
+                .entry(date.year())
+                .or_default()
+                .entry(date.month())
+                .or_default()
+                .push(item);
+        }
+    }
+
+    timeline
+}
+
+fn year_dir(root: &Path, year: i32) -> PathBuf {
+    root.join(year.to_string())
+}
+
+fn month_dir(root: &Path, year: i32, month: u32) -> PathBuf {
+    year_dir(root, year).join(format!("{month:02}"))
+}
+
+/// Fail if the archive pages would overwrite the pages of a collection named `archive`.
+pub fn check(config: &Config, collection: &Collection) -> Result<()> {
+    let path = config.toml.input.join("archive");
+
+    if config.has_template("archive.html") && collection.find(&path).is_some() {
+        return Err(anyhow!(
+            "Collection {:?} collides with the archive pages, rename it or remove archive.html",
+            path
+        ));
+    }
+
+    Ok(())
+}
+
+/// Return the pages written for the archive of `collection`.
+pub fn pages(config: &Config, collection: &Collection) -> Vec<PathBuf> {
+    if !config.has_template("archive.html") {
+        return vec![];
+    }
+
+    let root = config.toml.output.join("archive");
+    let mut pages = vec![root.join("index.html")];
+
+    for (year, months) in collect(collection) {
+        pages.push(year_dir(&root, year).join("index.html"));
+
+        for month in months.into_keys() {
+            pages.push(month_dir(&root, year, month).join("index.html"));
+        }
+    }
+
+    pages
+}
+
+/// Summarize `items` for a listing on the page in `dir`.
+fn summary(
+    name: String,
+    path: String,
+    items: &[&Item],
+    dir: &Path,
+    output: &Path,
+) -> Result<Summary> {
+    let first = items
+        .first()
+        .ok_or_else(|| anyhow!("{name} has no images"))?;
+
+    Ok(Summary {
+        name,
+        path,
+        count: items.len(),
+        thumbnail: relative(&first.thumbnail, dir, output)?,
+    })
+}
+
+fn render(config: &Config, dir: &Path, page: &Page) -> Result<()> {
+    let output = &config.toml.output;
+    create_dir_all(dir)?;
+
+    let mut context = config.context();
+    context.insert("archive", page);
+    context.insert("theme_url", &relative(&output.join("static"), dir, output)?);
+
+    Ok(write(
+        dir.join("index.html"),
+        config.templates.render("archive.html", &context)?,
+    )?)
+}
+
+/// Write pages grouping images by the year and month of their capture date into the `archive`
+/// directory of the output: `archive/index.html` listing all years, `archive/<year>/index.html`
+/// listing the months of a year and `archive/<year>/<month>/index.html` showing the images of a
+/// month. Pages are only written if the theme provides an `archive.html` template.
+pub fn write_pages(config: &Config, collection: &Collection) -> Result<()> {
+    if !config.has_template("archive.html") {
+        return Ok(());
+    }
+
+    let output = &config.toml.output;
+    let root = output.join("archive");
+    let timeline = collect(collection);
+    let mut years = Vec::new();
+
+    for (year, months) in &timeline {
+        let dir = year_dir(&root, *year);
+        let mut periods = Vec::new();
+
+        for (month, items) in months {
+            let month_dir = month_dir(&root, *year, *month);
+
+            let images = items
+                .iter()
+                .map(|item| {
+                    let parent = item.to.parent().ok_or_else(|| anyhow!("No parent"))?;
+                    Ok(Image::new(item, config)?
+                        .relative_to(&relative(parent, &month_dir, output)?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            render(
+                config,
+                &month_dir,
+                &Page {
+                    year: Some(*year),
+                    month: Some(format!("{month:02}")),
+                    periods: vec![],
+                    images,
+                },
+            )?;
+
+            periods.push(summary(
+                format!("{month:02}"),
+                format!("{month:02}/"),
+                items,
+                &dir,
+                output,
+            )?);
+        }
+
+        render(
+            config,
+            &dir,
+            &Page {
+                year: Some(*year),
+                month: None,
+                periods,
+                images: vec![],
+            },
+        )?;
+
+        let items = months.values().flatten().copied().collect::<Vec<_>>();
+        years.push(summary(
+            year.to_string(),
+            format!("{year}/"),
+            &items,
+            &root,
+            output,
+        )?);
+    }
+
+    render(
+        config,
+        &root,
+        &Page {
+            year: None,
+            month: None,
+            periods: years,
+            images: vec![],
+        },
+    )
+}
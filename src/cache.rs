@@ -0,0 +1,161 @@
+use crate::process::Target;
+use crate::Item;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{read_to_string, write, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+pub static CACHE_FILENAME: &str = ".splat-cache.json";
+
+/// State of a source image at the time its derived images were generated.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Entry {
+    /// SHA-256 hash of the source image.
+    hash: String,
+    /// Modification time of the source image in nanoseconds since the epoch.
+    modified: u64,
+    /// Size of the source image in bytes.
+    len: u64,
+    /// Settings used to generate each derived image keyed by its path relative to the output.
+    derivatives: BTreeMap<PathBuf, serde_json::Value>,
+}
+
+/// Manifest stored in the output directory recording the sources and settings of all derived
+/// images so that a build only redoes work affected by changed inputs or configuration.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Cache {
+    /// Entries keyed by the source path relative to the input.
+    entries: BTreeMap<PathBuf, Entry>,
+    /// Files written by builds since the last prune relative to the output. Only these are
+    /// removed once they are no longer produced.
+    #[serde(default)]
+    files: BTreeSet<PathBuf>,
+}
+
+fn hash(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+impl Entry {
+    /// Create the entry for the current state of `item` and its `targets`. The source is only
+    /// hashed again if its size or modification time differ from `previous`.
+    pub fn new(
+        item: &Item,
+        targets: &[Target],
+        output: &Path,
+        previous: Option<&Entry>,
+    ) -> Result<Self> {
+        let metadata = item.from.metadata()?;
+        let modified = u64::try_from(
+            metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        )?;
+        let len = metadata.len();
+
+        let hash = match previous {
+            Some(previous) if previous.modified == modified && previous.len == len => {
+                previous.hash.clone()
+            }
+            _ => hash(&item.from)?,
+        };
+
+        let derivatives = targets
+            .iter()
+            .map(|target| {
+                let path = target.dest.strip_prefix(output)?.to_owned();
+                Ok((path, serde_json::to_value(&target.operation)?))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            hash,
+            modified,
+            len,
+            derivatives,
+        })
+    }
+
+    /// Return `true` if `target` must be generated because it is missing or because the source
+    /// or the settings changed since `self` was recorded.
+    pub fn outdated(&self, current: &Entry, target: &Target, output: &Path) -> bool {
+        let Ok(path) = target.dest.strip_prefix(output) else {
+            return true;
+        };
+
+        self.hash != current.hash
+            || self.derivatives.get(path) != current.derivatives.get(path)
+            || Item::missing(target.dest, target.encodings())
+    }
+}
+
+impl Cache {
+    /// Load the cache from `output`. A missing or unreadable cache is treated as empty.
+    pub fn load(output: &Path) -> Self {
+        read_to_string(output.join(CACHE_FILENAME))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, output: &Path) -> Result<()> {
+        let path = output.join(CACHE_FILENAME);
+        write(&path, serde_json::to_string_pretty(self)?)
+            .context(format!("Failed to write {:?}", path))
+    }
+
+    pub fn get(&self, source: &Path) -> Option<&Entry> {
+        self.entries.get(source)
+    }
+
+    pub fn insert(&mut self, source: PathBuf, entry: Entry) {
+        self.entries.insert(source, entry);
+    }
+
+    /// Add the entries of `other`, replacing existing entries of the same sources.
+    pub fn extend(&mut self, other: Cache) {
+        self.entries.extend(other.entries);
+        self.files.extend(other.files);
+    }
+
+    /// Return a cache without entries that keeps the recorded files.
+    pub fn emptied(&self) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            files: self.files.clone(),
+        }
+    }
+
+    /// Return the recorded files below `output`.
+    pub fn files(&self, output: &Path) -> Vec<PathBuf> {
+        self.files.iter().map(|path| output.join(path)).collect()
+    }
+
+    /// Record those `files` that are below `output` as written, replacing all recorded files if
+    /// `replace` is set.
+    pub fn record(&mut self, files: &BTreeSet<PathBuf>, output: &Path, replace: bool) {
+        if replace {
+            self.files.clear();
+        }
+
+        self.files.extend(
+            files
+                .iter()
+                .filter_map(|path| path.strip_prefix(output).ok())
+                .map(Path::to_owned),
+        );
+    }
+}
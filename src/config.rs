@@ -1,23 +1,252 @@
 use crate::process::is_older;
 use anyhow::{anyhow, Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use image::ImageFormat;
+use serde::de::value::StrDeserializer;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::ffi::OsStr;
 use std::fs::read_to_string;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use tera::Tera;
 
 pub static TOML_FILENAME: &str = "splat.toml";
 
+/// How an image is scaled into the configured `width` and `height`. Images are never upscaled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// Scale to fit into `width` x `height` preserving the aspect ratio.
+    #[default]
+    Fit,
+    /// Scale and crop to exactly `width` x `height`.
+    Fill,
+    /// Scale to `width` preserving the aspect ratio.
+    Width,
+    /// Scale to `height` preserving the aspect ratio.
+    Height,
+    /// Scale the longest edge to the larger of `width` and `height`.
+    LongestEdge,
+}
+
+impl Mode {
+    fn fill() -> Self {
+        Self::Fill
+    }
+}
+
+/// Order of images or child collections on a collection page.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sort {
+    /// Oldest first by capture or else modification date.
+    Date,
+    /// By file name or title.
+    Name,
+    /// By file name or title in reverse.
+    NameDesc,
+    /// By file name or title with numbers compared by value, i.e. `img2` before `img10`.
+    Natural,
+    /// Newest first by EXIF capture date, undated last.
+    ExifDateDesc,
+    /// By file name, meant to be combined with an `Order:` list in the collection's `index.md`.
+    Manual,
+}
+
+impl Sort {
+    fn name() -> Self {
+        Self::Name
+    }
+
+    fn name_desc() -> Self {
+        Self::NameDesc
+    }
+}
+
+impl FromStr for Sort {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Self::deserialize(StrDeserializer::<serde::de::value::Error>::new(
+            value.trim(),
+        ))
+        .map_err(|err| anyhow!("{err}"))
+    }
+}
+
+/// Default order of images and child collections.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Sorting {
+    #[serde(default = "Sort::name")]
+    pub images: Sort,
+    #[serde(default = "Sort::name_desc")]
+    pub children: Sort,
+}
+
+impl Default for Sorting {
+    fn default() -> Self {
+        Self {
+            images: Sort::name(),
+            children: Sort::name_desc(),
+        }
+    }
+}
+
+/// Source image format accepted in the input directory.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Jpeg,
+    Png,
+    Webp,
+    Tiff,
+    Gif,
+}
+
+impl Format {
+    fn all() -> Vec<Self> {
+        vec![Self::Jpeg, Self::Png, Self::Webp, Self::Tiff, Self::Gif]
+    }
+
+    pub fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::Png => Some(Self::Png),
+            ImageFormat::WebP => Some(Self::Webp),
+            ImageFormat::Tiff => Some(Self::Tiff),
+            ImageFormat::Gif => Some(Self::Gif),
+            _ => None,
+        }
+    }
+
+    /// Format of derived images. Formats that browsers cannot display are converted to JPEG.
+    pub fn output(self) -> ImageFormat {
+        match self {
+            Self::Jpeg | Self::Tiff => ImageFormat::Jpeg,
+            Self::Png => ImageFormat::Png,
+            Self::Webp => ImageFormat::WebP,
+            Self::Gif => ImageFormat::Gif,
+        }
+    }
+
+    /// Return `true` if browsers can display the source image as is.
+    pub fn is_web_compatible(self) -> bool {
+        self != Self::Tiff
+    }
+}
+
+/// Additional output format stored next to a derived image.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Webp,
+    Avif,
+}
+
+impl Codec {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+}
+
+/// Encode a derived image additionally as `format` with the given `quality` between 1 and 100.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Encoding {
+    pub format: Codec,
+    #[serde(default = "Encoding::default_quality")]
+    pub quality: u8,
+}
+
+impl Encoding {
+    fn default_quality() -> u8 {
+        80
+    }
+}
+
+/// Chroma subsampling of JPEG output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Subsampling {
+    #[serde(rename = "4:4:4")]
+    Yuv444,
+    #[serde(rename = "4:2:2")]
+    Yuv422,
+    #[default]
+    #[serde(rename = "4:2:0")]
+    Yuv420,
+}
+
+/// Encoder settings for derived JPEG images. Derived WebP images use the `quality`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Jpeg {
+    /// Quality between 1 and 100.
+    #[serde(default = "Jpeg::default_quality")]
+    pub quality: u8,
+    #[serde(default)]
+    pub progressive: bool,
+    #[serde(default)]
+    pub subsampling: Subsampling,
+}
+
+impl Jpeg {
+    fn default_quality() -> u8 {
+        75
+    }
+}
+
+impl Default for Jpeg {
+    fn default() -> Self {
+        Self {
+            quality: Self::default_quality(),
+            progressive: false,
+            subsampling: Subsampling::default(),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Thumbnail {
     pub width: u32,
     pub height: u32,
+    #[serde(default = "Mode::fill")]
+    pub mode: Mode,
+    #[serde(default)]
+    pub encodings: Vec<Encoding>,
+    #[serde(flatten)]
+    pub jpeg: Jpeg,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Resize {
     pub width: u32,
     pub height: u32,
+    #[serde(default)]
+    pub mode: Mode,
+    #[serde(default)]
+    pub encodings: Vec<Encoding>,
+    #[serde(flatten)]
+    pub jpeg: Jpeg,
+}
+
+/// Additional named width full-size images are scaled to for responsive `srcset`s.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Size {
+    pub name: String,
+    pub width: u32,
+    #[serde(default)]
+    pub encodings: Vec<Encoding>,
+    #[serde(flatten)]
+    pub jpeg: Jpeg,
 }
 
 /// Generate `output` from `input` via the `command` which must contain Makefile style $@ and $< to
@@ -38,6 +267,20 @@ pub struct Theme {
     pub process: Option<Vec<Process>>,
 }
 
+/// Information about the whole site passed to every page as `site`.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Site {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// URL the site is published at.
+    pub base_url: Option<String>,
+    pub copyright: Option<String>,
+    /// Any other keys for use in the theme.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Toml {
     pub input: PathBuf,
@@ -45,12 +288,82 @@ pub struct Toml {
     pub theme: Theme,
     pub thumbnail: Thumbnail,
     pub resize: Option<Resize>,
+    /// Responsive sizes generated for each image.
+    #[serde(default)]
+    pub sizes: Vec<Size>,
+    /// Source image formats to include, defaults to all supported formats.
+    #[serde(default = "Format::all")]
+    pub formats: Vec<Format>,
+    /// Minimum rating of published images, unrated images count as 0.
+    pub min_rating: Option<i8>,
+    /// Order of images and child collections.
+    #[serde(default)]
+    pub sort: Sorting,
+    /// Glob patterns of files and directories in the input to skip.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Information about the site.
+    #[serde(default)]
+    pub site: Site,
+}
+
+/// Settings of a collection that its `index.md` can override for itself and its sub collections.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub thumbnail: Thumbnail,
+    pub resize: Option<Resize>,
+    #[serde(default)]
+    pub sort: Sorting,
+    pub min_rating: Option<i8>,
+}
+
+/// Merge `overrides` into `value`, replacing everything but objects which are merged by key.
+fn merge(value: &mut Value, overrides: &Value) {
+    match (value, overrides) {
+        (Value::Object(value), Value::Object(overrides)) => {
+            for (key, value_override) in overrides {
+                merge(value.entry(key).or_insert(Value::Null), value_override);
+            }
+        }
+        (value, overrides) => *value = overrides.clone(),
+    }
+}
+
+impl Settings {
+    /// Return the global settings of `toml`.
+    pub fn new(toml: &Toml) -> Self {
+        Self {
+            thumbnail: toml.thumbnail.clone(),
+            resize: toml.resize.clone(),
+            sort: toml.sort,
+            min_rating: toml.min_rating,
+        }
+    }
+
+    /// Return these settings with `overrides` applied. Tables are merged key by key so that only
+    /// the given values change, `resize = false` disables resizing.
+    pub fn merge(&self, overrides: &Map<String, Value>) -> Result<Self> {
+        let mut overrides = overrides.clone();
+
+        if let Some(resize @ Value::Bool(false)) = overrides.get_mut("resize") {
+            *resize = Value::Null;
+        }
+
+        let mut value = serde_json::to_value(self)?;
+        merge(&mut value, &Value::Object(overrides));
+        Ok(serde_json::from_value(value)?)
+    }
 }
 
 pub struct Config {
     pub toml: Toml,
     pub templates: Tera,
     pub static_path: Option<PathBuf>,
+    /// Include collections marked as draft.
+    pub drafts: bool,
+    /// Compiled `exclude` patterns.
+    pub exclude: GlobSet,
 }
 
 impl Config {
@@ -62,6 +375,20 @@ impl Config {
 
         Config::try_from(toml)
     }
+
+    /// Return a new template context holding the `site` information.
+    pub fn context(&self) -> tera::Context {
+        let mut context = tera::Context::new();
+        context.insert("site", &self.toml.site);
+        context
+    }
+
+    /// Return `true` if the theme provides the template `name`.
+    pub fn has_template(&self, name: &str) -> bool {
+        self.templates
+            .get_template_names()
+            .any(|template| template == name)
+    }
 }
 
 impl TryFrom<Toml> for Config {
@@ -77,15 +404,27 @@ impl TryFrom<Toml> for Config {
         let static_path = toml.theme.path.join("static");
         let static_path = static_path.exists().then_some(static_path);
 
+        let mut exclude = GlobSetBuilder::new();
+
+        for pattern in &toml.exclude {
+            exclude.add(Glob::new(pattern).context(format!("Invalid exclude {:?}", pattern))?);
+        }
+
         Ok(Config {
             toml,
             templates,
             static_path,
+            drafts: false,
+            exclude: exclude.build()?,
         })
     }
 }
 
 impl Process {
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
     /// Expand $< and $@ and run the command.
     pub fn run(&self) -> Result<()> {
         if self.output.exists() && !is_older(&self.output, &self.input)? {
@@ -116,3 +455,32 @@ impl Process {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::setup;
+    use serde_json::json;
+
+    #[test]
+    fn merge_settings() -> Result<()> {
+        let f = setup(Some((800, 600)))?;
+        let settings = Settings::new(&f.config.toml);
+        let merge = |overrides: Value| settings.merge(&serde_json::from_value(overrides)?);
+
+        let merged = merge(json!({"thumbnail": {"width": 900}, "resize": false}))?;
+        assert_eq!(
+            (merged.thumbnail.width, merged.thumbnail.height),
+            (900, 200)
+        );
+        assert_eq!(merged.thumbnail.mode, Mode::Fill);
+        assert!(merged.resize.is_none());
+
+        let merged = merge(json!({"resize": {"width": 400}, "sort": {"images": "date"}}))?;
+        assert_eq!(merged.resize.map(|resize| resize.width), Some(400));
+        assert_eq!(merged.sort.images, Sort::Date);
+
+        assert!(merge(json!({"size": 1})).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use ignore::gitignore::Gitignore;
+use std::path::Path;
+
+pub static IGNORE_FILENAME: &str = ".splatignore";
+
+/// Decides which entries of an input directory are skipped. Dot-files and dot-directories are
+/// always skipped, others if they match the `exclude` patterns of the configuration or the
+/// `.splatignore` files of the directory or its parents.
+#[derive(Clone, Default)]
+pub struct Exclude {
+    /// Matchers of the `.splatignore` files from the input directory down to the current one.
+    ignores: Vec<Gitignore>,
+}
+
+impl Exclude {
+    /// Return the rules for the entries of `dir`, adding its `.splatignore` if it has one.
+    pub fn enter(&self, dir: &Path) -> Result<Self> {
+        let path = dir.join(IGNORE_FILENAME);
+        let mut ignores = self.ignores.clone();
+
+        if path.exists() {
+            let (ignore, err) = Gitignore::new(&path);
+
+            if let Some(err) = err {
+                return Err(err).context(format!("Invalid pattern in {:?}", path));
+            }
+
+            ignores.push(ignore);
+        }
+
+        Ok(Self { ignores })
+    }
+
+    /// Return `true` if `path` is to be skipped.
+    pub fn is_excluded(&self, config: &Config, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+
+        if name.to_string_lossy().starts_with('.') {
+            return true;
+        }
+
+        let relative = path.strip_prefix(&config.toml.input).unwrap_or(path);
+
+        if config.exclude.is_match(relative) || config.exclude.is_match(name) {
+            return true;
+        }
+
+        // Later files take precedence and may whitelist entries ignored by earlier ones.
+        let is_dir = path.is_dir();
+
+        self.ignores
+            .iter()
+            .rev()
+            .map(|ignore| ignore.matched(path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::setup;
+    use globset::{Glob, GlobSetBuilder};
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn exclude_entries() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = f.config.toml.input.clone();
+        create_dir_all(input.join("a/b"))?;
+        create_dir_all(input.join("raw"))?;
+        write(input.join(IGNORE_FILENAME), "*.bak\n")?;
+        write(input.join("a").join(IGNORE_FILENAME), "!keep.bak\n")?;
+
+        let mut builder = GlobSetBuilder::new();
+        builder.add(Glob::new("@eaDir")?);
+        builder.add(Glob::new("raw/*.jpg")?);
+        f.config.exclude = builder.build()?;
+
+        let root = Exclude::default().enter(&input)?;
+        let nested = root.enter(&input.join("a"))?.enter(&input.join("a/b"))?;
+
+        assert!(root.is_excluded(&f.config, &input.join(".git")));
+        assert!(root.is_excluded(&f.config, &input.join("x.bak")));
+        assert!(root.is_excluded(&f.config, &input.join("raw/x.jpg")));
+        assert!(!root.is_excluded(&f.config, &input.join("x.jpg")));
+        assert!(nested.is_excluded(&f.config, &input.join("a/b/@eaDir")));
+        assert!(nested.is_excluded(&f.config, &input.join("a/b/x.bak")));
+        assert!(!nested.is_excluded(&f.config, &input.join("a/b/keep.bak")));
+        Ok(())
+    }
+}
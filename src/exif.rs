@@ -0,0 +1,244 @@
+use ::exif::{Context, Field, In, Reader, Tag, Value};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Rating tag written by Windows and some cameras, not defined by the EXIF standard.
+const RATING: Tag = Tag(Context::Tiff, 0x4746);
+
+/// Photo details read from the EXIF data of an image. Tags that are not present in the image are
+/// left out.
+#[derive(Clone, Default, Serialize)]
+pub struct Exif {
+    /// Camera make and model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    /// Lens make and model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lens: Option<String>,
+    /// Focal length, e.g. `35 mm`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_length: Option<String>,
+    /// Aperture as f-number, e.g. `f/2.8`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aperture: Option<String>,
+    /// Shutter speed, e.g. `1/250 s`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposure_time: Option<String>,
+    /// ISO sensitivity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iso: Option<u32>,
+    /// Capture date formatted as `YYYY-MM-DDTHH:MM:SS`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Rating from 0 to 5 stars.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<i8>,
+}
+
+fn field(exif: &::exif::Exif, tag: Tag) -> Option<&Field> {
+    exif.get_field(tag, In::PRIMARY)
+}
+
+fn ascii(exif: &::exif::Exif, tag: Tag) -> Option<String> {
+    match &field(exif, tag)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+fn rational(exif: &::exif::Exif, tag: Tag) -> Option<(u32, u32)> {
+    match &field(exif, tag)?.value {
+        Value::Rational(values) => values
+            .first()
+            .filter(|value| value.denom != 0)
+            .map(|value| (value.num, value.denom)),
+        _ => None,
+    }
+}
+
+/// Join make and model unless the model already starts with the make as some vendors do.
+fn make_and_model(make: Option<String>, model: Option<String>) -> Option<String> {
+    match (make, model) {
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{make} {model}")),
+        (make, model) => make.or(model),
+    }
+}
+
+/// Format a decimal without a trailing `.0`.
+fn decimal(value: f64) -> String {
+    let rounded = (value * 10.0).round() / 10.0;
+
+    if rounded.fract() == 0.0 {
+        format!("{rounded:.0}")
+    } else {
+        format!("{rounded:.1}")
+    }
+}
+
+fn exposure_time((num, denom): (u32, u32)) -> String {
+    if num < denom {
+        format!("1/{} s", (f64::from(denom) / f64::from(num.max(1))).round())
+    } else {
+        format!("{} s", decimal(f64::from(num) / f64::from(denom)))
+    }
+}
+
+/// Turn EXIF's `YYYY:MM:DD HH:MM:SS` into `YYYY-MM-DDTHH:MM:SS`.
+fn date(value: &str) -> Option<String> {
+    let (date, time) = value.split_once(' ')?;
+    let date = date.replace(':', "-");
+
+    let time = time.get(..8)?;
+
+    (date.len() == 10).then(|| format!("{date}T{time}"))
+}
+
+impl From<&::exif::Exif> for Exif {
+    fn from(exif: &::exif::Exif) -> Self {
+        let iso = field(exif, Tag::PhotographicSensitivity).and_then(|f| f.value.get_uint(0));
+
+        Self {
+            camera: make_and_model(ascii(exif, Tag::Make), ascii(exif, Tag::Model)),
+            lens: make_and_model(ascii(exif, Tag::LensMake), ascii(exif, Tag::LensModel)),
+            focal_length: rational(exif, Tag::FocalLength)
+                .map(|(num, denom)| format!("{} mm", decimal(f64::from(num) / f64::from(denom)))),
+            aperture: rational(exif, Tag::FNumber)
+                .map(|(num, denom)| format!("f/{}", decimal(f64::from(num) / f64::from(denom)))),
+            exposure_time: rational(exif, Tag::ExposureTime).map(exposure_time),
+            iso,
+            date: ascii(exif, Tag::DateTimeOriginal)
+                .or_else(|| ascii(exif, Tag::DateTime))
+                .and_then(|value| date(&value)),
+            rating: field(exif, RATING)
+                .and_then(|f| f.value.get_uint(0))
+                .and_then(|rating| i8::try_from(rating).ok()),
+        }
+    }
+}
+
+impl Exif {
+    /// Read EXIF data from `path`. Missing or broken EXIF data is not an error but results in an
+    /// empty [`Exif`].
+    pub fn from_path(path: &Path) -> Self {
+        let Ok(file) = File::open(path) else {
+            return Self::default();
+        };
+
+        Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .map(|exif| Self::from(&exif))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::exif::experimental::Writer;
+    use ::exif::Rational;
+    use anyhow::Result;
+    use std::fs::{read, write};
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    /// Insert an APP1 segment containing `fields` right after the SOI marker of `jpeg`.
+    pub fn with_exif(jpeg: &[u8], fields: &[Field]) -> Result<Vec<u8>> {
+        let mut writer = Writer::new();
+
+        for field in fields {
+            writer.push_field(field);
+        }
+
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false)?;
+        let tiff = tiff.into_inner();
+
+        let mut result = jpeg[..2].to_vec();
+        result.extend_from_slice(&[0xff, 0xe1]);
+        result.extend_from_slice(&u16::try_from(tiff.len() + 8)?.to_be_bytes());
+        result.extend_from_slice(b"Exif\0\0");
+        result.extend_from_slice(&tiff);
+        result.extend_from_slice(&jpeg[2..]);
+        Ok(result)
+    }
+
+    fn ascii_field(tag: Tag, value: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+        }
+    }
+
+    fn rational_field(tag: Tag, num: u32, denom: u32) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(vec![Rational { num, denom }]),
+        }
+    }
+
+    #[test]
+    fn read_exif() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("exif.jpg");
+
+        let fields = [
+            ascii_field(Tag::Make, "Canon"),
+            ascii_field(Tag::Model, "Canon EOS R5"),
+            ascii_field(Tag::LensModel, "RF 35mm F1.8"),
+            ascii_field(Tag::DateTimeOriginal, "2025:06:14 12:34:56"),
+            rational_field(Tag::FocalLength, 35, 1),
+            rational_field(Tag::FNumber, 28, 10),
+            rational_field(Tag::ExposureTime, 1, 250),
+            Field {
+                tag: Tag::PhotographicSensitivity,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![400]),
+            },
+            Field {
+                tag: RATING,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![4]),
+            },
+        ];
+
+        write(&path, with_exif(&read("data/test.jpg")?, &fields)?)?;
+
+        let exif = Exif::from_path(&path);
+        assert_eq!(exif.camera.as_deref(), Some("Canon EOS R5"));
+        assert_eq!(exif.lens.as_deref(), Some("RF 35mm F1.8"));
+        assert_eq!(exif.focal_length.as_deref(), Some("35 mm"));
+        assert_eq!(exif.aperture.as_deref(), Some("f/2.8"));
+        assert_eq!(exif.exposure_time.as_deref(), Some("1/250 s"));
+        assert_eq!(exif.iso, Some(400));
+        assert_eq!(exif.date.as_deref(), Some("2025-06-14T12:34:56"));
+        assert_eq!(exif.rating, Some(4));
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_date() {
+        assert_eq!(
+            date("2025:06:14 12:34:56+02").as_deref(),
+            Some("2025-06-14T12:34:56")
+        );
+        assert_eq!(date("2025:06:14 12:34"), None);
+        assert_eq!(date("2025:06:14 12:34:5\u{fffd}"), None);
+    }
+
+    #[test]
+    fn missing_exif_is_empty() -> Result<()> {
+        let exif = Exif::from_path(Path::new("data/test.jpg"));
+        assert!(exif.camera.is_none());
+        assert!(exif.iso.is_none());
+        assert!(exif.date.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use crate::xmp::segment;
+use std::path::Path;
+
+/// Header of the APP13 segment holding Photoshop image resources.
+const APP13_HEADER: &[u8] = b"Photoshop 3.0\0";
+
+/// Image resource containing IPTC-NAA records.
+const IPTC_RESOURCE: u16 = 0x0404;
+
+/// Record and dataset numbers of IPTC keywords.
+const KEYWORDS: (u8, u8) = (2, 25);
+
+/// Return the IPTC-NAA records found in Photoshop image `resources`.
+fn records(resources: &[u8]) -> Option<&[u8]> {
+    let mut rest = resources;
+
+    while rest.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([*rest.get(4)?, *rest.get(5)?]);
+
+        // The name is a Pascal string padded to an even length including the length byte.
+        let name_length = usize::from(*rest.get(6)?);
+        let offset = 6 + (name_length + 2) / 2 * 2;
+        let size = rest.get(offset..offset + 4)?;
+        let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let data = rest.get(offset + 4..offset + 4 + size)?;
+
+        if id == IPTC_RESOURCE {
+            return Some(data);
+        }
+
+        rest = rest.get(offset + 4 + size + size % 2..)?;
+    }
+
+    None
+}
+
+/// Return the values of the IPTC `dataset` from `records`.
+fn values(mut records: &[u8], dataset: (u8, u8)) -> Vec<String> {
+    let mut values = Vec::new();
+
+    // Extended datasets with lengths above 32767 bytes are not supported.
+    while let [0x1c, record, number, high, low, rest @ ..] = records {
+        let length = usize::from(u16::from_be_bytes([*high, *low]));
+
+        let Some(value) = rest.get(..length).filter(|_| length < 0x8000) else {
+            break;
+        };
+
+        if (*record, *number) == dataset {
+            values.push(String::from_utf8_lossy(value).trim().to_owned());
+        }
+
+        records = &rest[length..];
+    }
+
+    values
+}
+
+/// Read IPTC keywords embedded in the JPEG at `path`. Missing or broken IPTC data results in no
+/// keywords.
+pub fn keywords(path: &Path) -> Vec<String> {
+    segment(path, 0xed, APP13_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|resources| records(&resources).map(|records| values(records, KEYWORDS)))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|keyword| !keyword.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return Photoshop image resources with an IPTC resource containing `keywords`.
+    fn resources(keywords: &[&str]) -> Vec<u8> {
+        let mut records = vec![0x1c, 1, 90, 0, 3, 0x1b, b'%', b'G'];
+
+        for keyword in keywords {
+            records.extend_from_slice(&[0x1c, 2, 25]);
+            records.extend_from_slice(&(keyword.len() as u16).to_be_bytes());
+            records.extend_from_slice(keyword.as_bytes());
+        }
+
+        let mut resources = b"8BIM\x03\xed\x00\x00\x00\x00\x00\x02ab".to_vec();
+        resources.extend_from_slice(b"8BIM\x04\x04\x00\x00");
+        resources.extend_from_slice(&(records.len() as u32).to_be_bytes());
+        resources.extend_from_slice(&records);
+        resources
+    }
+
+    #[test]
+    fn read_keywords() {
+        let resources = resources(&["sea", "boat"]);
+        let records = records(&resources).unwrap();
+        assert_eq!(values(records, KEYWORDS), ["sea", "boat"]);
+    }
+
+    #[test]
+    fn missing_iptc_resource() {
+        assert!(records(b"8BIM\x03\xed\x00\x00\x00\x00\x00\x02ab").is_none());
+        assert!(records(b"8BIM\x04\x04\x00\x00\x00\x00\x00\xff").is_none());
+    }
+}
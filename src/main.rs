@@ -1,25 +1,85 @@
+mod archive;
+mod cache;
 mod config;
+mod exclude;
+mod exif;
+mod iptc;
 mod metadata;
 mod process;
-
-use anyhow::{anyhow, Result};
+mod prune;
+mod serve;
+mod sort;
+mod tags;
+mod watch;
+mod xmp;
+
+use anyhow::{anyhow, Context, Result};
+use cache::{Cache, Entry};
+use chrono::{DateTime, Local, NaiveDateTime};
 use clap::Parser;
-use config::Config;
-use metadata::Metadata;
-use process::{copy_recursively, is_older, process, Process};
+use config::{Config, Encoding, Format, Mode, Settings};
+use exclude::Exclude;
+use exif::Exif;
+use metadata::{Caption, Metadata};
+use process::{
+    copy_recursively, detect_format, dimensions, encoded_path, output_size, process, targets,
+    Process,
+};
 use rayon::prelude::*;
 use serde::Serialize;
+use slug::slugify;
+use sort::Sortable;
+use std::collections::HashSet;
 use std::fs::{create_dir_all, read_dir, write};
 use std::io::{self, Write};
+use std::iter;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread;
+use watch::{watch, Gallery};
+use xmp::Xmp;
 
 #[derive(Parser)]
 #[clap(name = "splat", about = "Static photo gallery generator")]
 enum Commands {
     #[clap(about = "Build static gallery", visible_alias = "b")]
-    Build,
+    Build {
+        /// Remove files from the output that are no longer produced.
+        #[clap(long)]
+        prune: bool,
+
+        /// Only list the files that --prune would remove.
+        #[clap(long, requires = "prune")]
+        dry_run: bool,
+
+        /// Include collections marked as draft.
+        #[clap(long)]
+        drafts: bool,
+    },
+
+    #[clap(about = "Remove files from the output that are no longer produced")]
+    Clean {
+        /// Only list the files that would be removed.
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Keep the output of collections marked as draft.
+        #[clap(long)]
+        drafts: bool,
+    },
+
+    #[clap(
+        about = "Build, serve on localhost and rebuild on changes",
+        visible_alias = "s"
+    )]
+    Serve {
+        /// Port to listen on.
+        #[clap(long, default_value_t = 8000)]
+        port: u16,
+    },
+
+    #[clap(about = "Build and rebuild on changes", visible_alias = "w")]
+    Watch,
 
     #[clap(
         about = "Create new splat.toml config and example theme",
@@ -36,6 +96,24 @@ pub struct Item {
     to: PathBuf,
     /// Thumbnail generated from `from`.
     thumbnail: PathBuf,
+    /// Responsive variants of `to`, one for each configured size.
+    sizes: Vec<PathBuf>,
+    /// Format of `from`.
+    format: Format,
+    /// Dimensions of `from` as displayed, `None` if it cannot be read.
+    dimensions: Option<(u32, u32)>,
+    /// EXIF data read from `from`.
+    exif: Exif,
+    /// Caption read from a sidecar file of `from`.
+    caption: Caption,
+    /// XMP data read from a sidecar file of `from` or embedded in it.
+    xmp: Xmp,
+    /// Tags from XMP, IPTC, the sidecar file and the collection.
+    tags: Vec<String>,
+    /// Capture date from EXIF data or else the modification time of `from`.
+    date: Option<NaiveDateTime>,
+    /// Settings of the collection containing `from`.
+    settings: Arc<Settings>,
 }
 
 /// A [`Collection`] contains either other [`Collection`]s or a bunch of [`Item`]s.
@@ -49,19 +127,66 @@ struct Collection {
     metadata: Metadata,
     /// Path to the process thumbnail.
     thumbnail: PathBuf,
+    /// Settings of this collection after applying the overrides of its `index.md`.
+    settings: Arc<Settings>,
+    /// Rules for skipping entries of this collection's directory.
+    exclude: Exclude,
+}
+
+/// Alternative encoding of an image for `<picture>` elements.
+#[derive(Clone, Serialize)]
+struct Source {
+    /// Path to the encoded image.
+    path: PathBuf,
+    /// MIME type of the encoding.
+    #[serde(rename = "type")]
+    mime_type: &'static str,
+}
+
+/// Responsive variant of an image.
+#[derive(Clone, Serialize)]
+struct Variant<'a> {
+    /// Name of the configured size.
+    name: &'a str,
+    /// Path to the scaled image.
+    path: PathBuf,
+    /// Width of the scaled image.
+    width: u32,
+    /// Height of the scaled image.
+    height: u32,
+    /// Alternative encodings of the scaled image.
+    sources: Vec<Source>,
 }
 
 /// A fullsize image, its thumbnail and its image dimensions as used in the HTML templates.
 #[derive(Clone, Serialize)]
 struct Image<'a> {
     /// Path to the image.
-    path: &'a str,
+    path: String,
+    /// Alternative encodings of the image.
+    sources: Vec<Source>,
     /// Path to the thumbnail.
     thumbnail: PathBuf,
+    /// Alternative encodings of the thumbnail.
+    thumbnail_sources: Vec<Source>,
+    /// Responsive variants of the image.
+    sizes: Vec<Variant<'a>>,
     /// Width of the image.
     width: u32,
     /// Height of the image.
     height: u32,
+    /// EXIF data of the source image.
+    exif: &'a Exif,
+    /// Title from the sidecar file.
+    title: Option<&'a str>,
+    /// Rendered description from the sidecar file.
+    description: &'a str,
+    /// XMP data of the source image.
+    xmp: &'a Xmp,
+    /// Tags of the image.
+    tags: &'a [String],
+    /// Path to the page of the image if the theme has an `image.html` template.
+    page: Option<String>,
 }
 
 /// Individual subcollection.
@@ -82,6 +207,19 @@ struct Link<'a> {
     path: String,
 }
 
+/// Group of images listed on an overview page.
+#[derive(Serialize)]
+struct Summary {
+    /// Name of the group.
+    name: String,
+    /// Path to the page of the group.
+    path: String,
+    /// Number of images in the group.
+    count: usize,
+    /// Thumbnail of the first image in the group.
+    thumbnail: PathBuf,
+}
+
 /// Context passed to the tera template.
 #[derive(Serialize)]
 struct Output<'a> {
@@ -95,6 +233,8 @@ struct Output<'a> {
     children: Vec<Child<'a>>,
     /// Images part of this collection.
     images: Vec<Image<'a>>,
+    /// Custom keys of the collection's `index.md`.
+    extra: &'a serde_json::Map<String, serde_json::Value>,
 }
 
 /// Compute breadcrumb links from a list of strings.
@@ -118,6 +258,15 @@ fn breadcrumbs_to_links(breadcrumbs: &[String]) -> Vec<Link<'_>> {
     links
 }
 
+/// Return `path` relative to the directory `from`, both below the `output` directory.
+fn relative(path: &Path, from: &Path, output: &Path) -> Result<PathBuf> {
+    let depth = from.strip_prefix(output)?.components().count();
+
+    Ok(iter::repeat_n("..", depth)
+        .collect::<PathBuf>()
+        .join(path.strip_prefix(output)?))
+}
+
 fn output_path_to_root(output: &Path) -> PathBuf {
     let mut path = PathBuf::new();
 
@@ -128,16 +277,35 @@ fn output_path_to_root(output: &Path) -> PathBuf {
     path
 }
 
+impl Source {
+    fn list(path: &Path, encodings: &[Encoding]) -> Vec<Self> {
+        encodings
+            .iter()
+            .map(|encoding| Self {
+                path: encoded_path(path, encoding.format),
+                mime_type: encoding.format.mime_type(),
+            })
+            .collect()
+    }
+}
+
 impl<'a> Image<'a> {
-    fn new(item: &'a Item) -> Result<Self> {
-        let (width, height) = image::image_dimensions(&item.to)?;
+    fn new(item: &'a Item, config: &'a Config) -> Result<Self> {
+        let size = item
+            .dimensions
+            .ok_or_else(|| anyhow!("{:?} is not a valid image", item.from))?;
+
+        let (width, height) = item.settings.resize.as_ref().map_or(size, |resize| {
+            output_size(resize.mode, size, (resize.width, resize.height))
+        });
 
         let path = item
             .to
             .file_name()
             .ok_or_else(|| anyhow!("{:?} is not a file", item.to))?
             .to_str()
-            .ok_or_else(|| anyhow!("Failed to stringify {:?}", item.to))?;
+            .ok_or_else(|| anyhow!("Failed to stringify {:?}", item.to))?
+            .to_owned();
 
         let thumbnail = PathBuf::from("thumbnails").join(
             item.thumbnail
@@ -145,41 +313,186 @@ impl<'a> Image<'a> {
                 .ok_or_else(|| anyhow!("{:?} has no file name", item.thumbnail))?,
         );
 
+        let sources = item
+            .settings
+            .resize
+            .as_ref()
+            .map(|resize| Source::list(Path::new(&path), &resize.encodings))
+            .unwrap_or_default();
+
+        let thumbnail_sources = Source::list(&thumbnail, &item.settings.thumbnail.encodings);
+
+        let sizes = config
+            .toml
+            .sizes
+            .iter()
+            .map(|variant| {
+                let (width, height) = output_size(Mode::Width, size, (variant.width, 0));
+                let path = PathBuf::from("sizes").join(&variant.name).join(&path);
+
+                Variant {
+                    name: &variant.name,
+                    sources: Source::list(&path, &variant.encodings),
+                    path,
+                    width,
+                    height,
+                }
+            })
+            .collect();
+
+        let page = config
+            .has_template("image.html")
+            .then(|| format!("{path}.html"));
+
         Ok(Self {
             thumbnail,
+            thumbnail_sources,
+            sizes,
             path,
+            sources,
             width,
             height,
+            exif: &item.exif,
+            title: item.caption.title.as_deref(),
+            description: &item.caption.description,
+            xmp: &item.xmp,
+            tags: &item.tags,
+            page,
         })
     }
+
+    /// Prefix all paths with `prefix` to show the image on a page outside of its collection.
+    fn relative_to(mut self, prefix: &Path) -> Self {
+        self.path = prefix.join(&self.path).to_string_lossy().into_owned();
+        self.thumbnail = prefix.join(&self.thumbnail);
+        self.page = self
+            .page
+            .map(|page| prefix.join(page).to_string_lossy().into_owned());
+
+        for variant in &mut self.sizes {
+            variant.path = prefix.join(&variant.path);
+        }
+
+        let sources = self
+            .sources
+            .iter_mut()
+            .chain(&mut self.thumbnail_sources)
+            .chain(
+                self.sizes
+                    .iter_mut()
+                    .flat_map(|variant| &mut variant.sources),
+            );
+
+        for source in sources {
+            source.path = prefix.join(&source.path);
+        }
+
+        self
+    }
 }
 
 impl Item {
-    fn new(path: PathBuf, config: &Config) -> Result<Self> {
-        let to = config
+    fn new(
+        path: PathBuf,
+        format: Format,
+        config: &Config,
+        settings: Arc<Settings>,
+    ) -> Result<Self> {
+        let mut to = config
             .toml
             .output
             .join(path.strip_prefix(&config.toml.input)?);
 
-        Ok(Self {
-            thumbnail: to
-                .parent()
-                .ok_or_else(|| anyhow!("No parent"))?
-                .join("thumbnails")
-                .join(path.file_name().ok_or_else(|| anyhow!("Path ends in .."))?),
+        // Keep the source extension so that `a.tif` and `a.jpg` do not end up in the same file.
+        if !format.is_web_compatible() {
+            let mut converted = to.into_os_string();
+            converted.push(".jpg");
+            to = PathBuf::from(converted);
+        }
+
+        let parent = to.parent().ok_or_else(|| anyhow!("No parent"))?;
+        let file_name = to.file_name().ok_or_else(|| anyhow!("Path ends in .."))?;
+
+        let sizes = config
+            .toml
+            .sizes
+            .iter()
+            .map(|size| parent.join("sizes").join(&size.name).join(file_name))
+            .collect();
+
+        let caption =
+            Caption::from_path(&path).context(format!("Failed to read caption of {:?}", path))?;
+        let xmp = Xmp::from_path(&path);
+        let tags = [
+            xmp.keywords.clone(),
+            iptc::keywords(&path),
+            caption.tags.clone(),
+        ]
+        .concat();
+
+        let exif = Exif::from_path(&path);
+        let date = exif
+            .date
+            .as_deref()
+            .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").ok())
+            .or_else(|| {
+                let modified = path.metadata().ok()?.modified().ok()?;
+                Some(DateTime::<Local>::from(modified).naive_local())
+            });
+
+        let mut item = Self {
+            thumbnail: parent.join("thumbnails").join(file_name),
+            sizes,
             to,
+            format,
+            dimensions: dimensions(&path).ok(),
+            exif,
+            date,
+            caption,
+            xmp,
+            tags: vec![],
             from: path,
-        })
+            settings,
+        };
+
+        item.add_tags(&tags);
+        Ok(item)
+    }
+
+    /// Add those `tags` that are not yet present under a different spelling.
+    fn add_tags(&mut self, tags: &[String]) {
+        for tag in tags {
+            let slug = slugify(tag);
+
+            if !self.tags.iter().any(|existing| slugify(existing) == slug) {
+                self.tags.push(tag.clone());
+            }
+        }
+    }
+
+    /// Return `true` if `path` or one of its additional `encodings` does not exist.
+    fn missing(path: &Path, encodings: &[Encoding]) -> bool {
+        !path.exists()
+            || encodings
+                .iter()
+                .any(|encoding| !encoded_path(path, encoding.format).exists())
+    }
+
+    /// Rating from XMP or else EXIF data.
+    fn rating(&self) -> Option<i8> {
+        self.xmp.rating.or(self.exif.rating)
     }
 
-    fn needs_update(&self) -> bool {
-        !self.to.exists()
-            || is_older(&self.to, &self.from).unwrap_or_default()
-            || !self.thumbnail.exists()
+    /// Return `true` if this item is rated at least `min_rating`. Unrated items count as 0.
+    fn is_published(&self, min_rating: Option<i8>) -> bool {
+        min_rating.is_none_or(|min_rating| self.rating().unwrap_or(0) >= min_rating)
     }
 
-    fn thumbnail_outdated(&self) -> Result<bool> {
-        is_older(&self.thumbnail, &self.from)
+    /// Return the path of the page written for this item with the `image.html` template.
+    fn page(&self) -> PathBuf {
+        let mut page = self.to.clone().into_os_string();
+        page.push(".html");
+        page.into()
     }
 }
 
@@ -195,7 +508,7 @@ impl<'a> Child<'a> {
             .file_name()
             .ok_or_else(|| anyhow!("{:?} has no filename", collection.thumbnail))?;
 
-        let thumbnail = collection
+        let mut thumbnail = collection
             .thumbnail
             .strip_prefix(path)?
             .parent()
@@ -203,6 +516,12 @@ impl<'a> Child<'a> {
             .join("thumbnails")
             .join(filename);
 
+        if detect_format(&collection.thumbnail).is_some_and(|format| !format.is_web_compatible()) {
+            let mut converted = thumbnail.into_os_string();
+            converted.push(".jpg");
+            thumbnail = PathBuf::from(converted);
+        }
+
         let subdir = collection
             .path
             .file_name()
@@ -220,45 +539,73 @@ impl<'a> Child<'a> {
 
 impl Collection {
     fn new(current: &Path, config: &Config) -> Result<Option<Self>> {
+        Self::read(
+            current,
+            config,
+            &Settings::new(&config.toml),
+            &Exclude::default(),
+        )
+    }
+
+    /// Read the collection at `current` with the `settings` and `exclude` rules inherited from its
+    /// parent.
+    fn read(
+        current: &Path,
+        config: &Config,
+        settings: &Settings,
+        exclude: &Exclude,
+    ) -> Result<Option<Self>> {
+        let metadata = Metadata::from_path(current)?;
+
+        if metadata.draft && !config.drafts {
+            return Ok(None);
+        }
+
+        let mut settings = settings.merge(&metadata.settings).context(format!(
+            "Invalid settings in {:?}",
+            current.join("index.md")
+        ))?;
+        settings.min_rating = metadata.min_rating.or(settings.min_rating);
+        settings.sort.images = metadata.sort.unwrap_or(settings.sort.images);
+        settings.sort.children = metadata.sort_children.unwrap_or(settings.sort.children);
+        let settings = Arc::new(settings);
+        let exclude = exclude.enter(current)?;
+
         let collections: Vec<Collection> = read_dir(current)?
             .filter_map(Result::ok)
-            .filter(|entry| entry.path().is_dir())
-            .map(|entry| Collection::new(&entry.path(), config))
-            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && !exclude.is_excluded(config, path))
+            .map(|path| Collection::read(&path, config, &settings, &exclude))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
             .flatten()
             .collect();
 
         let items: Vec<Item> = read_dir(current)?
             .filter_map(Result::ok)
-            .filter(|e| {
-                e.path().is_file()
-                    && e.path().extension().is_some_and(|ext| {
-                        ext == "JPG" || ext == "jpg" || ext == "JPEG" || ext == "jpeg"
-                    })
+            .map(|e| e.path())
+            .filter(|path| path.is_file() && !exclude.is_excluded(config, path))
+            .filter_map(|path| {
+                detect_format(&path)
+                    .filter(|format| config.toml.formats.contains(format))
+                    .map(|format| (path, format))
+            })
+            .map(|(path, format)| {
+                let mut item = Item::new(path, format, config, settings.clone())?;
+                item.add_tags(&metadata.tags);
+                Ok(item)
+            })
+            .filter(|item| {
+                item.as_ref()
+                    .map_or(true, |item| item.is_published(settings.min_rating))
             })
-            .map(|e| Item::new(e.path(), config))
             .collect::<Result<Vec<_>>>()?;
 
         if items.is_empty() && collections.is_empty() {
             return Ok(None);
         }
 
-        let metadata = Metadata::from_path(current)?;
-
-        // Determine thumbnail for this collection. We prioritize the one specified in the metadata
-        // over the first item in this collection over the thumbnail of the first child collection.
-        let thumbnail = metadata
-            .thumbnail
-            .as_ref()
-            .cloned()
-            .or_else(|| {
-                items
-                    .first()
-                    .map_or(collections.first().map(|c| c.thumbnail.clone()), |item| {
-                        Some(item.from.clone())
-                    })
-            })
-            .ok_or_else(|| anyhow!("No thumbnail path"))?;
+        let thumbnail = select_thumbnail(&metadata, &items, &collections)?;
 
         Ok(Some(Collection {
             path: current.to_owned(),
@@ -266,9 +613,46 @@ impl Collection {
             items,
             metadata,
             thumbnail,
+            settings,
+            exclude,
         }))
     }
 
+    /// Return the collection at `path` if it is this or one of the sub collections.
+    fn find(&self, path: &Path) -> Option<&Collection> {
+        if self.path == path {
+            return Some(self);
+        }
+
+        self.collections
+            .iter()
+            .find(|child| path.starts_with(&child.path))?
+            .find(path)
+    }
+
+    /// Mutable variant of [`Collection::find`].
+    fn find_mut(&mut self, path: &Path) -> Option<&mut Collection> {
+        if self.path == path {
+            return Some(self);
+        }
+
+        self.collections
+            .iter_mut()
+            .find(|child| path.starts_with(&child.path))?
+            .find_mut(path)
+    }
+
+    /// Determine the thumbnails of this and all sub collections again after items or metadata
+    /// changed.
+    fn update_thumbnails(&mut self) -> Result<()> {
+        for child in &mut self.collections {
+            child.update_thumbnails()?;
+        }
+
+        self.thumbnail = select_thumbnail(&self.metadata, &self.items, &self.collections)?;
+        Ok(())
+    }
+
     /// Return all items from this and all sub collections.
     fn items(&self) -> Vec<&Item> {
         let mut items: Vec<_> = self.items.iter().collect();
@@ -279,10 +663,57 @@ impl Collection {
 
         items
     }
+
+    /// Items of this and all sub collections that are not hidden, as shown in listings.
+    fn listed(&self) -> Vec<&Item> {
+        let mut items: Vec<_> = self.items.iter().collect();
+
+        for child in self.collections.iter().filter(|c| !c.metadata.hidden) {
+            items.extend(child.listed());
+        }
+
+        items
+    }
+}
+
+/// Determine thumbnail for a collection. We prioritize the one specified in the metadata, if it is
+/// one of the published items of the collection or its sub collections, over the highest rated
+/// item in this collection over the thumbnail of the first child collection that is not hidden,
+/// else of the first hidden one. Among equally rated items the first one wins.
+fn select_thumbnail(
+    metadata: &Metadata,
+    items: &[Item],
+    collections: &[Collection],
+) -> Result<PathBuf> {
+    metadata
+        .thumbnail
+        .as_ref()
+        .filter(|thumbnail| {
+            items
+                .iter()
+                .chain(collections.iter().flat_map(Collection::items))
+                .any(|item| &&item.from == thumbnail)
+        })
+        .cloned()
+        .or_else(|| {
+            items
+                .iter()
+                .rev()
+                .max_by_key(|item| item.rating().unwrap_or(0))
+                .map_or(
+                    collections
+                        .iter()
+                        .find(|c| !c.metadata.hidden)
+                        .or_else(|| collections.first())
+                        .map(|c| c.thumbnail.clone()),
+                    |item| Some(item.from.clone()),
+                )
+        })
+        .ok_or_else(|| anyhow!("No thumbnail path"))
 }
 
-/// Build the gallery and all required assets.
-fn build(config: &Config) -> Result<()> {
+/// Build the gallery and all required assets and return the built collection.
+fn build(config: &Config) -> Result<Collection> {
     if !config.toml.input.exists() {
         return Err(anyhow!("{:?} does not exist", config.toml.input));
     }
@@ -306,45 +737,94 @@ fn build(config: &Config) -> Result<()> {
     let collection =
         Collection::new(&config.toml.input, config)?.ok_or_else(|| anyhow!("No images found"))?;
 
-    let items = collection
-        .items()
-        .into_iter()
-        .filter(|item| item.needs_update())
-        .collect::<Vec<_>>();
+    check_listings(config, &collection)?;
+
+    let output = &config.toml.output;
+    process_items(config, &collection.items(), &Cache::load(output))?.save(output)?;
 
-    let num_items = items.len();
+    print!("  Writing HTML pages ...");
+    // TODO: make "home" configurable
+    let mut breadcrumbs: Vec<String> = vec![String::from("home")];
+    write_html(config, &collection, &mut breadcrumbs, &config.toml.output)?;
+    write_listings(config, &collection)?;
+    prune::record(config, &collection)?;
+    println!("\x1B[2K\r\x1B[0;32m✔\x1B[0;m Wrote HTML pages");
+
+    Ok(collection)
+}
+
+/// Generate the outdated derived images of `items` and return the cache entries of `items` along
+/// with the files recorded in `cache`. Entries of items that failed to process are taken from
+/// `cache` to retry them next time.
+fn process_items(config: &Config, items: &[&Item], cache: &Cache) -> Result<Cache> {
+    let output = &config.toml.output;
+    let mut updated = cache.emptied();
     let (sender, receiver) = mpsc::channel::<Result<()>>();
+    let mut processes = Vec::new();
+
+    // Hashing reads every source, so check the items in parallel as well.
+    let checked = items
+        .par_iter()
+        .map(|item| {
+            let source = item.from.strip_prefix(&config.toml.input)?.to_owned();
+            let previous = cache.get(&source);
+            let targets = targets(item, config);
+            let entry = Entry::new(item, &targets, output, previous)?;
+
+            let targets = targets
+                .into_iter()
+                .filter(|target| {
+                    previous.is_none_or(|previous| previous.outdated(&entry, target, output))
+                })
+                .collect::<Vec<_>>();
 
-    let processes = items
-        .into_iter()
-        .map(|item| Process {
-            config,
-            item,
-            sender: sender.clone(),
+            Ok((*item, source, entry, targets))
         })
-        .collect::<Vec<_>>();
+        .collect::<Result<Vec<_>>>()?;
+
+    for (item, source, entry, targets) in checked {
+        if targets.is_empty() {
+            updated.insert(source, entry);
+        } else {
+            let process = Process {
+                item,
+                targets,
+                sender: sender.clone(),
+            };
+
+            processes.push((source, entry, process));
+        }
+    }
 
+    let num_items = processes.len();
     thread::spawn(move || display_progress(num_items, receiver));
 
-    processes.into_par_iter().for_each(|p| {
-        if let Err(err) = process(&p) {
-            eprintln!("failed to process an image: {err:?}");
-        }
-    });
+    let processed = processes
+        .into_par_iter()
+        .map(|(source, entry, p)| match process(&p) {
+            Ok(true) => (source, Some(entry)),
+            Ok(false) => (source, None),
+            Err(err) => {
+                eprintln!("failed to process an image: {err:?}");
+                (source, None)
+            }
+        })
+        .collect::<Vec<_>>();
 
-    print!("  Writing HTML pages ...");
-    // TODO: make "home" configurable
-    let mut breadcrumbs: Vec<String> = vec![String::from("home")];
-    write_html(config, &collection, &mut breadcrumbs, &config.toml.output)?;
-    println!("\x1B[2K\r\x1B[0;32m✔\x1B[0;m Wrote HTML pages");
+    // Keep the previous state of failed items to retry them next time.
+    for (source, entry) in processed {
+        if let Some(entry) = entry.or_else(|| cache.get(&source).cloned()) {
+            updated.insert(source, entry);
+        }
+    }
 
-    Ok(())
+    Ok(updated)
 }
 
 fn display_progress(num_items: usize, receiver: mpsc::Receiver<Result<()>>) {
     let spinner = ["⠖", "⠲", "⠴", "⠦"].iter().cycle();
 
-    for (spinner, remaining) in spinner.zip(num_items..0) {
+    for (spinner, remaining) in spinner.zip((1..=num_items).rev()) {
         print!(
             "\x1B[2K\r\x1B[0;36m{spinner}\x1B[0;m Processing {} images ...",
             remaining
@@ -394,49 +874,143 @@ fn write_html(
         breadcrumbs.remove(breadcrumbs.len() - 1);
     }
 
-    let mut images = collection
-        .items
-        .iter()
-        .map(Image::new)
-        .collect::<Result<Vec<_>, _>>()?;
+    write_page(config, collection, breadcrumbs, output)
+}
+
+/// Write out the page of `collection` without its children into `output`.
+fn write_page(
+    config: &Config,
+    collection: &Collection,
+    breadcrumbs: &[String],
+    output: &Path,
+) -> Result<()> {
+    if !output.exists() {
+        create_dir_all(output)?;
+    }
 
-    images.sort_by(|a, b| a.thumbnail.cmp(&b.thumbnail));
+    let order = &collection.metadata.order;
 
-    let mut children = collection
+    let mut items = collection.items.iter().collect::<Vec<_>>();
+    sort::sort(&mut items, collection.settings.sort.images);
+    sort::order(&mut items, order);
+
+    let mut collections = collection
         .collections
         .iter()
-        .map(Child::from)
+        .filter(|child| !child.metadata.hidden)
+        .collect::<Vec<_>>();
+    sort::sort(&mut collections, collection.settings.sort.children);
+    sort::order(&mut collections, order);
+
+    let files = items
+        .iter()
+        .map(Sortable::file)
+        .chain(collections.iter().map(Sortable::file))
+        .collect::<HashSet<_>>();
+
+    for name in order.iter().filter(|name| !files.contains(*name)) {
+        println!(
+            "\x1B[2K\r\x1B[0;33mW\x1B[0;m {:?} listed in Order of {:?} does not exist",
+            name, collection.path
+        );
+    }
+
+    let images = items
+        .into_iter()
+        .map(|item| Image::new(item, config))
         .collect::<Result<Vec<_>, _>>()?;
 
-    children.sort_by(|a, b| b.title.cmp(a.title));
+    let children = collections
+        .into_iter()
+        .map(Child::from)
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let mut context = tera::Context::new();
+    let mut context = config.context();
     let breadcrumbs = breadcrumbs_to_links(breadcrumbs);
 
-    context.insert(
-        "collection",
-        &Output {
-            title: &collection.metadata.title,
-            description: &collection.metadata.description,
-            breadcrumbs,
-            children,
-            images,
-        },
-    );
+    let collection = Output {
+        title: &collection.metadata.title,
+        description: &collection.metadata.description,
+        breadcrumbs,
+        children,
+        images,
+        extra: &collection.metadata.extra,
+    };
+
+    context.insert("collection", &collection);
 
     let static_path = output_path_to_root(output).join("static");
     context.insert("theme_url", &static_path);
 
     let index_html = output.join("index.html");
+    write(index_html, config.templates.render("index.html", &context)?)?;
+
+    // Pages of single images link to their neighbours in the order of the collection page.
+    for (index, image) in collection.images.iter().enumerate() {
+        let Some(page) = &image.page else {
+            continue;
+        };
+
+        context.insert("image", image);
+        context.insert(
+            "previous",
+            &index
+                .checked_sub(1)
+                .and_then(|index| collection.images.get(index)),
+        );
+        context.insert("next", &collection.images.get(index + 1));
+
+        write(
+            output.join(page),
+            config.templates.render("image.html", &context)?,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Fail if pages listing images across collections would overwrite collection pages.
+fn check_listings(config: &Config, collection: &Collection) -> Result<()> {
+    tags::check(config, collection)?;
+    archive::check(config, collection)
+}
+
+/// Write the pages listing images across collections, i.e. tag and archive pages.
+fn write_listings(config: &Config, collection: &Collection) -> Result<()> {
+    tags::write_pages(config, collection)?;
+    archive::write_pages(config, collection)
+}
+
+fn run_build(prune: bool, dry_run: bool, drafts: bool) -> Result<()> {
+    let config = Config {
+        drafts,
+        ..Config::read()?
+    };
+    let collection = build(&config)?;
 
-    Ok(write(
-        index_html,
-        config.templates.render("index.html", &context)?,
-    )?)
+    if prune {
+        prune::prune(&config, &collection, dry_run)?;
+    }
+
+    Ok(())
+}
+
+fn run_watch() -> Result<()> {
+    let mut gallery = Gallery::new(Config::read()?)?;
+    println!("\x1B[2K\r\x1B[0;32m✔\x1B[0;m Watching for changes");
+
+    watch(&mut gallery, |_| Ok(()))
 }
 
-fn run_build() -> Result<()> {
-    build(&Config::read()?)
+fn run_clean(dry_run: bool, drafts: bool) -> Result<()> {
+    let config = Config {
+        drafts,
+        ..Config::read()?
+    };
+    let collection =
+        Collection::new(&config.toml.input, &config)?.ok_or_else(|| anyhow!("No images found"))?;
+
+    prune::prune(&config, &collection, dry_run)
 }
 
 /// Write out configuration and default theme.
@@ -482,7 +1056,14 @@ fn main() {
     let commands = Commands::parse();
 
     let result = match commands {
-        Commands::Build => run_build(),
+        Commands::Build {
+            prune,
+            dry_run,
+            drafts,
+        } => run_build(prune, dry_run, drafts),
+        Commands::Clean { dry_run, drafts } => run_clean(dry_run, drafts),
+        Commands::Serve { port } => serve::serve(port),
+        Commands::Watch => run_watch(),
         Commands::New => run_new(),
     };
 
@@ -492,25 +1073,27 @@ fn main() {
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
-    use image;
+    use crate::exif::tests::with_exif;
+    use crate::xmp::tests::{with_xmp, xmp};
+    use chrono::TimeZone;
     use metadata::tests::METADATA;
-    use std::fs::{copy, create_dir, create_dir_all, write, File};
+    use std::fs::{copy, create_dir, create_dir_all, read, read_to_string, write, File};
     use tempfile::{tempdir, TempDir};
 
-    struct Fixture {
-        config: Config,
+    pub struct Fixture {
+        pub config: Config,
         _dir: TempDir,
     }
 
     impl Fixture {
         fn collect(&self) -> Result<Option<Collection>> {
-            Ok(Collection::new(&self.config.toml.input, &self.config)?)
+            Collection::new(&self.config.toml.input, &self.config)
         }
     }
 
-    fn setup(resize: Option<(u32, u32)>) -> Result<Fixture> {
+    pub fn setup(resize: Option<(u32, u32)>) -> Result<Fixture> {
         let dir = tempdir()?;
         let input = dir.path().join("input");
         let output = dir.path().join("output");
@@ -532,13 +1115,23 @@ mod tests {
             thumbnail: config::Thumbnail {
                 width: 300,
                 height: 200,
+                mode: config::Mode::Fill,
+                encodings: vec![],
+                jpeg: config::Jpeg::default(),
             },
-            resize: resize.and_then(|r| {
-                Some(config::Resize {
-                    width: r.0,
-                    height: r.1,
-                })
+            resize: resize.map(|r| config::Resize {
+                width: r.0,
+                height: r.1,
+                mode: config::Mode::Fit,
+                encodings: vec![],
+                jpeg: config::Jpeg::default(),
             }),
+            sizes: vec![],
+            formats: vec![Format::Jpeg, Format::Png, Format::Tiff],
+            min_rating: None,
+            sort: config::Sorting::default(),
+            exclude: vec![],
+            site: config::Site::default(),
         };
 
         Ok(Fixture {
@@ -576,12 +1169,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn detect_formats() -> Result<()> {
+        let f = setup(None)?;
+        File::create(f.config.toml.input.join("a.PNG"))?;
+        File::create(f.config.toml.input.join("b.gif"))?;
+        copy("data/test.jpg", f.config.toml.input.join("c"))?;
+
+        let collection = f.collect()?.unwrap();
+        let mut formats = collection
+            .items
+            .iter()
+            .map(|item| item.format)
+            .collect::<Vec<_>>();
+        formats.sort_by_key(|format| format!("{format:?}"));
+        assert_eq!(formats, vec![Format::Jpeg, Format::Png]);
+        Ok(())
+    }
+
     #[test]
     fn choose_metadata_thumbnail() -> Result<()> {
         let f = setup(None)?;
-        File::create(&f.config.toml.input.join("1.jpg"))?;
-        File::create(&f.config.toml.input.join("2.jpg"))?;
-        File::create(&f.config.toml.input.join("3.jpg"))?;
+        File::create(f.config.toml.input.join("1.jpg"))?;
+        File::create(f.config.toml.input.join("2.jpg"))?;
+        File::create(f.config.toml.input.join("3.jpg"))?;
         write(f.config.toml.input.join("index.md"), "Thumbnail: 2.jpg")?;
 
         let collection = f.collect()?.unwrap();
@@ -604,6 +1215,19 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn ignore_unpublished_metadata_thumbnail() -> Result<()> {
+        let f = setup(None)?;
+        let image_path = f.config.toml.input.join("test.jpg");
+        File::create(&image_path)?;
+        File::create(f.config.toml.input.join("notes.txt"))?;
+        write(f.config.toml.input.join("index.md"), "Thumbnail: notes.txt")?;
+
+        let collection = f.collect()?.unwrap();
+        assert_eq!(collection.thumbnail, image_path);
+        Ok(())
+    }
+
     #[test]
     fn choose_root_thumbnail_on_conflict() -> Result<()> {
         let f = setup(None)?;
@@ -619,6 +1243,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn choose_nested_metadata_thumbnail() -> Result<()> {
+        let f = setup(None)?;
+        let input = &f.config.toml.input;
+        create_dir_all(input.join("sub"))?;
+        create_dir_all(input.join("draft"))?;
+        File::create(input.join("y.jpg"))?;
+        File::create(input.join("sub/x.jpg"))?;
+        File::create(input.join("draft/z.jpg"))?;
+        write(input.join("draft/index.md"), "Draft: true")?;
+
+        write(input.join("index.md"), "Thumbnail: sub/x.jpg")?;
+        let collection = f.collect()?.unwrap();
+        assert_eq!(collection.thumbnail, input.join("sub/x.jpg"));
+
+        write(input.join("index.md"), "Thumbnail: draft/z.jpg")?;
+        let collection = f.collect()?.unwrap();
+        assert_eq!(collection.thumbnail, input.join("y.jpg"));
+        Ok(())
+    }
+
     #[test]
     fn choose_subdir_thumbnail() -> Result<()> {
         let f = setup(None)?;
@@ -712,6 +1357,821 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn process_resize_never_upscales() -> Result<()> {
+        let f = setup(Some((1800, 1800)))?;
+        copy("data/test.jpg", f.config.toml.input.join("test.jpg"))?;
+
+        build(&f.config)?;
+        let copy_dims = image::image_dimensions(f.config.toml.output.join("test.jpg"))?;
+        assert_eq!(copy_dims, (900, 600));
+
+        Ok(())
+    }
+
+    #[test]
+    fn process_convert_tiff() -> Result<()> {
+        let f = setup(None)?;
+        image::open("data/test.jpg")?.save(f.config.toml.input.join("test.tif"))?;
+
+        build(&f.config)?;
+        let copy_name = f.config.toml.output.join("test.tif.jpg");
+        let thumb_name = f.config.toml.output.join("thumbnails/test.tif.jpg");
+
+        let format = image::ImageReader::open(&copy_name)?
+            .with_guessed_format()?
+            .format();
+        assert_eq!(format, Some(image::ImageFormat::Jpeg));
+        assert_eq!(image::image_dimensions(copy_name)?, (900, 600));
+        assert_eq!(image::image_dimensions(thumb_name)?, (300, 200));
+
+        Ok(())
+    }
+
+    #[test]
+    fn converted_and_jpeg_with_same_stem() -> Result<()> {
+        let f = setup(None)?;
+        let input = &f.config.toml.input;
+        copy("data/test.jpg", input.join("a.jpg"))?;
+        image::open("data/test.jpg")?
+            .rotate90()
+            .save(input.join("a.tif"))?;
+
+        let collection = build(&f.config)?;
+        let output = &f.config.toml.output;
+        assert_eq!(collection.items.len(), 2);
+        assert_ne!(collection.items[0].to, collection.items[1].to);
+        assert_eq!(image::image_dimensions(output.join("a.jpg"))?, (900, 600));
+        assert_eq!(
+            image::image_dimensions(output.join("a.tif.jpg"))?,
+            (600, 900)
+        );
+        assert!(output.join("thumbnails/a.tif.jpg").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn converted_child_thumbnail() -> Result<()> {
+        let f = setup(None)?;
+        let subdir = f.config.toml.input.join("t");
+        create_dir(&subdir)?;
+        image::open("data/test.jpg")?.save(subdir.join("a.tif"))?;
+
+        let collection = f.collect()?.unwrap();
+        let child = Child::from(&collection.collections[0])?;
+        assert_eq!(child.thumbnail, Path::new("t/thumbnails/a.tif.jpg"));
+        Ok(())
+    }
+
+    #[test]
+    fn process_encodings() -> Result<()> {
+        let mut f = setup(Some((600, 400)))?;
+        let webp = config::Encoding {
+            format: config::Codec::Webp,
+            quality: 75,
+        };
+        let avif = config::Encoding {
+            format: config::Codec::Avif,
+            quality: 50,
+        };
+        f.config.toml.thumbnail.encodings = vec![webp.clone(), avif];
+        f.config.toml.resize.as_mut().unwrap().encodings = vec![webp];
+        copy("data/test.jpg", f.config.toml.input.join("test.jpg"))?;
+
+        build(&f.config)?;
+        let output = &f.config.toml.output;
+        assert_eq!(
+            image::image_dimensions(output.join("test.jpg.webp"))?,
+            (600, 400)
+        );
+        assert!(output.join("thumbnails/test.jpg.webp").exists());
+        assert!(output.join("thumbnails/test.jpg.avif").exists());
+
+        let collection = f.collect()?.unwrap();
+        let image = Image::new(&collection.items[0], &f.config)?;
+        assert_eq!(image.sources.len(), 1);
+        assert_eq!(image.sources[0].path, PathBuf::from("test.jpg.webp"));
+        assert_eq!(image.sources[0].mime_type, "image/webp");
+        assert_eq!(image.thumbnail_sources.len(), 2);
+        assert_eq!(
+            image.thumbnail_sources[1].path,
+            PathBuf::from("thumbnails/test.jpg.avif")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn process_webp_lossy() -> Result<()> {
+        let mut f = setup(Some((600, 400)))?;
+        f.config.toml.formats.push(Format::Webp);
+        image::open("data/test.jpg")?.save(f.config.toml.input.join("test.webp"))?;
+
+        build(&f.config)?;
+        let output = &f.config.toml.output;
+
+        // Lossy WebP files hold a "VP8 " chunk, lossless ones a "VP8L" chunk.
+        for path in ["test.webp", "thumbnails/test.webp"] {
+            let data = read(output.join(path))?;
+            assert_eq!(&data[12..16], b"VP8 ");
+        }
+
+        assert_eq!(
+            image::image_dimensions(output.join("test.webp"))?,
+            (600, 400)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn process_sizes() -> Result<()> {
+        let mut f = setup(None)?;
+        f.config.toml.sizes = vec![
+            config::Size {
+                name: String::from("small"),
+                width: 480,
+                encodings: vec![],
+                jpeg: config::Jpeg::default(),
+            },
+            config::Size {
+                name: String::from("large"),
+                width: 1920,
+                encodings: vec![],
+                jpeg: config::Jpeg::default(),
+            },
+        ];
+        copy("data/test.jpg", f.config.toml.input.join("test.jpg"))?;
+
+        build(&f.config)?;
+        let output = &f.config.toml.output;
+        assert_eq!(
+            image::image_dimensions(output.join("sizes/small/test.jpg"))?,
+            (480, 320)
+        );
+        assert_eq!(
+            image::image_dimensions(output.join("sizes/large/test.jpg"))?,
+            (900, 600)
+        );
+
+        let collection = f.collect()?.unwrap();
+        let image = Image::new(&collection.items[0], &f.config)?;
+        assert_eq!(image.sizes[0].name, "small");
+        assert_eq!(image.sizes[0].path, PathBuf::from("sizes/small/test.jpg"));
+        assert_eq!((image.sizes[0].width, image.sizes[0].height), (480, 320));
+        assert_eq!((image.sizes[1].width, image.sizes[1].height), (900, 600));
+
+        Ok(())
+    }
+
+    #[test]
+    fn process_jpeg_settings() -> Result<()> {
+        let mut f = setup(None)?;
+        copy("data/test.jpg", f.config.toml.input.join("test.jpg"))?;
+        build(&f.config)?;
+        let thumb_name = f.config.toml.output.join("thumbnails/test.jpg");
+        let default_size = thumb_name.metadata()?.len();
+
+        f.config.toml.thumbnail.jpeg = config::Jpeg {
+            quality: 95,
+            progressive: true,
+            subsampling: config::Subsampling::Yuv444,
+        };
+        build(&f.config)?;
+
+        assert!(thumb_name.metadata()?.len() > default_size);
+        assert_eq!(image::image_dimensions(&thumb_name)?, (300, 200));
+
+        // Progressive JPEGs contain a SOF2 marker.
+        assert!(read(&thumb_name)?.windows(2).any(|w| w == [0xff, 0xc2]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn image_dimensions_without_output() -> Result<()> {
+        let mut f = setup(Some((600, 600)))?;
+        f.config.toml.sizes = vec![config::Size {
+            name: String::from("small"),
+            width: 300,
+            encodings: vec![],
+            jpeg: config::Jpeg::default(),
+        }];
+        copy("data/test.jpg", f.config.toml.input.join("test.jpg"))?;
+
+        // Dimensions are derived from the source and the configuration without any output.
+        let collection = f.collect()?.unwrap();
+        let image = Image::new(&collection.items[0], &f.config)?;
+        assert_eq!((image.width, image.height), (600, 400));
+        assert_eq!((image.sizes[0].width, image.sizes[0].height), (300, 200));
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_skips_unchanged() -> Result<()> {
+        let f = setup(None)?;
+        copy("data/test.jpg", f.config.toml.input.join("test.jpg"))?;
+        build(&f.config)?;
+
+        let thumb_name = f.config.toml.output.join("thumbnails/test.jpg");
+        let modified = thumb_name.metadata()?.modified()?;
+        assert!(f.config.toml.output.join(cache::CACHE_FILENAME).exists());
+
+        build(&f.config)?;
+        assert_eq!(thumb_name.metadata()?.modified()?, modified);
+        Ok(())
+    }
+
+    #[test]
+    fn cache_detects_changed_settings() -> Result<()> {
+        let mut f = setup(None)?;
+        copy("data/test.jpg", f.config.toml.input.join("test.jpg"))?;
+        build(&f.config)?;
+
+        f.config.toml.thumbnail.width = 150;
+        f.config.toml.thumbnail.height = 100;
+        build(&f.config)?;
+
+        let thumb_name = f.config.toml.output.join("thumbnails/test.jpg");
+        assert_eq!(image::image_dimensions(thumb_name)?, (150, 100));
+        Ok(())
+    }
+
+    #[test]
+    fn cache_detects_restored_source() -> Result<()> {
+        let f = setup(None)?;
+        let source = f.config.toml.input.join("test.jpg");
+        image::open("data/test.jpg")?
+            .crop_imm(0, 0, 600, 600)
+            .save(&source)?;
+        build(&f.config)?;
+
+        // Restore an "older" image whose modification time precedes the generated output.
+        let modified = f
+            .config
+            .toml
+            .output
+            .join("test.jpg")
+            .metadata()?
+            .modified()?;
+        copy("data/test.jpg", &source)?;
+        File::options()
+            .write(true)
+            .open(&source)?
+            .set_modified(modified - std::time::Duration::from_secs(3600))?;
+        build(&f.config)?;
+
+        let copy_dims = image::image_dimensions(f.config.toml.output.join("test.jpg"))?;
+        assert_eq!(copy_dims, (900, 600));
+        Ok(())
+    }
+
+    #[test]
+    fn image_pages() -> Result<()> {
+        let mut f = setup(None)?;
+        copy("data/test.jpg", f.config.toml.input.join("a.jpg"))?;
+        copy("data/test.jpg", f.config.toml.input.join("b.jpg"))?;
+        copy("data/test.jpg", f.config.toml.input.join("c.jpg"))?;
+
+        write(
+            f.config.toml.theme.path.join("templates/image.html"),
+            "{{ previous.path | default(value='') }} < {{ image.path }} > {{ next.path | default(value='') }}",
+        )?;
+        f.config.templates.full_reload()?;
+
+        let collection = build(&f.config)?;
+        let output = &f.config.toml.output;
+
+        assert_eq!(
+            read_to_string(output.join("a.jpg.html"))?,
+            " < a.jpg > b.jpg"
+        );
+        assert_eq!(
+            read_to_string(output.join("b.jpg.html"))?,
+            "a.jpg < b.jpg > c.jpg"
+        );
+        assert_eq!(
+            read_to_string(output.join("c.jpg.html"))?,
+            "b.jpg < c.jpg > "
+        );
+        assert!(prune::stale(&f.config, &collection)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn listed_order() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = &f.config.toml.input;
+        create_dir(input.join("x"))?;
+        create_dir(input.join("y"))?;
+        copy("data/test.jpg", input.join("x/x.jpg"))?;
+        copy("data/test.jpg", input.join("y/y.jpg"))?;
+
+        for name in ["img1.jpg", "img2.jpg", "img10.jpg"] {
+            copy("data/test.jpg", input.join(name))?;
+        }
+
+        for name in ["img2.jpg", "img10.jpg"] {
+            copy("data/test.jpg", input.join("y").join(name))?;
+        }
+
+        write(
+            input.join("index.md"),
+            "Order: img10.jpg, x/, gone.jpg\nSort: natural",
+        )?;
+        write(
+            f.config.toml.theme.path.join("templates/index.html"),
+            "{% for i in collection.images %}{{ i.path }} {% endfor %}\
+             {% for c in collection.children %}{{ c.title }} {% endfor %}",
+        )?;
+        f.config.templates.full_reload()?;
+
+        build(&f.config)?;
+        assert_eq!(
+            read_to_string(f.config.toml.output.join("index.html"))?,
+            "img10.jpg img1.jpg img2.jpg x y "
+        );
+        // Sort: is inherited, Order: is not.
+        assert_eq!(
+            read_to_string(f.config.toml.output.join("y/index.html"))?,
+            "img2.jpg img10.jpg y.jpg "
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn image_captions() -> Result<()> {
+        let f = setup(None)?;
+        let input = &f.config.toml.input;
+        copy("data/test.jpg", input.join("a.jpg"))?;
+        copy("data/test.jpg", input.join("b.jpg"))?;
+        copy("data/test.jpg", input.join("c.jpg"))?;
+        write(input.join("a.md"), METADATA)?;
+        write(input.join("b.jpg.md"), "Just *text*.")?;
+
+        let collection = f.collect()?.unwrap();
+        let caption = |name: &str| -> Result<(Option<String>, String)> {
+            let item = collection
+                .items
+                .iter()
+                .find(|item| item.from == input.join(name))
+                .unwrap();
+            let image = Image::new(item, &f.config)?;
+            Ok((image.title.map(str::to_owned), image.description.to_owned()))
+        };
+
+        assert_eq!(
+            caption("a.jpg")?,
+            (
+                Some("foo".to_owned()),
+                "<p>Description.</p>\n<p>Next paragraph.</p>\n".to_owned()
+            )
+        );
+        assert_eq!(
+            caption("b.jpg")?,
+            (None, "<p>Just <em>text</em>.</p>\n".to_owned())
+        );
+        assert_eq!(caption("c.jpg")?, (None, String::new()));
+        Ok(())
+    }
+
+    #[test]
+    fn thumbnail_prefers_rated_items() -> Result<()> {
+        let f = setup(None)?;
+        let input = &f.config.toml.input;
+        copy("data/test.jpg", input.join("a.jpg"))?;
+        copy("data/test.jpg", input.join("b.jpg"))?;
+        write(
+            input.join("c.jpg"),
+            with_xmp(&read("data/test.jpg")?, &xmp(3, &[]))?,
+        )?;
+        assert_eq!(f.collect()?.unwrap().thumbnail, input.join("c.jpg"));
+
+        write(input.join("b.jpg.xmp"), xmp(5, &[]))?;
+        assert_eq!(f.collect()?.unwrap().thumbnail, input.join("b.jpg"));
+        Ok(())
+    }
+
+    #[test]
+    fn min_rating_excludes_items() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = f.config.toml.input.clone();
+        let subdir = input.join("all");
+        create_dir(&subdir)?;
+        copy("data/test.jpg", input.join("a.jpg"))?;
+        copy("data/test.jpg", input.join("b.jpg"))?;
+        copy("data/test.jpg", subdir.join("c.jpg"))?;
+        write(input.join("a.xmp"), xmp(3, &[]))?;
+        write(input.join("b.xmp"), xmp(-1, &[]))?;
+
+        assert_eq!(f.collect()?.unwrap().items().len(), 3);
+
+        f.config.toml.min_rating = Some(1);
+        let collection = f.collect()?.unwrap();
+        let items = collection.items();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].from, input.join("a.jpg"));
+
+        write(subdir.join("index.md"), "MinRating: 0")?;
+        let collection = f.collect()?.unwrap();
+        let sources = collection
+            .items()
+            .iter()
+            .map(|item| item.from.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(sources.len(), 2);
+        assert!(sources.contains(&subdir.join("c.jpg")));
+        Ok(())
+    }
+
+    #[test]
+    fn tag_pages() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = &f.config.toml.input;
+        let subdir = input.join("s");
+        create_dir(&subdir)?;
+        copy("data/test.jpg", input.join("a.jpg"))?;
+        copy("data/test.jpg", subdir.join("b.jpg"))?;
+        write(input.join("a.xmp"), xmp(0, &["Sea", "Boat", "!!!"]))?;
+        write(subdir.join("b.md"), "Tags: sea")?;
+        write(subdir.join("index.md"), "Tags: Holiday")?;
+
+        let templates = f.config.toml.theme.path.join("templates");
+        write(
+            templates.join("tag.html"),
+            "{{ tag.name }}:{% for image in tag.images %} {{ image.path }}{% endfor %}",
+        )?;
+        write(
+            templates.join("tags.html"),
+            "{% for tag in tags %}{{ tag.path }} {{ tag.count }} {{ tag.thumbnail }};{% endfor %}",
+        )?;
+        f.config.templates.full_reload()?;
+
+        let collection = build(&f.config)?;
+        let output = f.config.toml.output.join("tags");
+
+        assert_eq!(
+            read_to_string(output.join("sea/index.html"))?,
+            "Sea: ../../a.jpg ../../s/b.jpg"
+        );
+        assert_eq!(
+            read_to_string(output.join("holiday/index.html"))?,
+            "Holiday: ../../s/b.jpg"
+        );
+        assert_eq!(
+            read_to_string(output.join("index.html"))?,
+            "boat/ 1 ../thumbnails/a.jpg;holiday/ 1 ../s/thumbnails/b.jpg;sea/ 2 ../thumbnails/a.jpg;"
+        );
+        assert!(prune::stale(&f.config, &collection)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn collection_settings_are_inherited() -> Result<()> {
+        let f = setup(Some((300, 300)))?;
+        let input = &f.config.toml.input;
+        create_dir_all(input.join("pano/inner"))?;
+        copy("data/test.jpg", input.join("a.jpg"))?;
+        copy("data/test.jpg", input.join("pano/inner/b.jpg"))?;
+        write(
+            input.join("pano/index.md"),
+            "+++\n[settings]\nresize = false\n[settings.thumbnail]\nwidth = 600\nheight = 100\n+++\n",
+        )?;
+
+        build(&f.config)?;
+        let output = &f.config.toml.output;
+        assert_eq!(image::image_dimensions(output.join("a.jpg"))?, (300, 200));
+        assert_eq!(
+            image::image_dimensions(output.join("thumbnails/a.jpg"))?,
+            (300, 200)
+        );
+        assert_eq!(
+            image::image_dimensions(output.join("pano/inner/b.jpg"))?,
+            (900, 600)
+        );
+        assert_eq!(
+            image::image_dimensions(output.join("pano/inner/thumbnails/b.jpg"))?,
+            (600, 100)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn site_in_every_page() -> Result<()> {
+        let mut f = setup(None)?;
+        copy("data/test.jpg", f.config.toml.input.join("a.jpg"))?;
+        write(f.config.toml.input.join("a.md"), "Tags: sea")?;
+        f.config.toml.site = toml::from_str(
+            "title = \"Photos\"\nbase_url = \"https://example.com\"\nmastodon = \"@me\"",
+        )?;
+        assert_eq!(f.config.toml.site.title.as_deref(), Some("Photos"));
+
+        let templates = f.config.toml.theme.path.join("templates");
+
+        for name in ["index", "image", "tag", "tags", "archive"] {
+            write(
+                templates.join(format!("{name}.html")),
+                "{{ site.title }} {{ site.base_url }} {{ site.mastodon }}",
+            )?;
+        }
+
+        f.config.templates.full_reload()?;
+        build(&f.config)?;
+
+        let output = &f.config.toml.output;
+        let date = Local::now().format("%Y/%m");
+
+        for page in [
+            PathBuf::from("index.html"),
+            PathBuf::from("a.jpg.html"),
+            PathBuf::from("tags/sea/index.html"),
+            PathBuf::from("tags/index.html"),
+            PathBuf::from(format!("archive/{date}/index.html")),
+        ] {
+            assert_eq!(
+                read_to_string(output.join(page))?,
+                "Photos https://example.com @me"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn excluded_entries_are_skipped() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = f.config.toml.input.clone();
+
+        for dir in [".thumbnails", "@eaDir", "keep"] {
+            create_dir(input.join(dir))?;
+            copy("data/test.jpg", input.join(dir).join("a.jpg"))?;
+        }
+
+        copy("data/test.jpg", input.join("keep/b.jpg"))?;
+        write(input.join("keep/.splatignore"), "b.jpg")?;
+        f.config.toml.exclude = vec![String::from("@eaDir")];
+        f.config = f.config.toml.try_into()?;
+
+        let collection = f.collect()?.unwrap();
+        let sources = collection
+            .items()
+            .iter()
+            .map(|item| item.from.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(sources, [input.join("keep/a.jpg")]);
+        Ok(())
+    }
+
+    #[test]
+    fn tags_collide_with_collection() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = &f.config.toml.input;
+        create_dir(input.join("tags"))?;
+        copy("data/test.jpg", input.join("tags/a.jpg"))?;
+        build(&f.config)?;
+
+        write(f.config.toml.theme.path.join("templates/tags.html"), "")?;
+        f.config.templates.full_reload()?;
+        assert!(build(&f.config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn archive_collides_with_collection() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = &f.config.toml.input;
+        create_dir(input.join("archive"))?;
+        copy("data/test.jpg", input.join("archive/a.jpg"))?;
+        build(&f.config)?;
+
+        write(f.config.toml.theme.path.join("templates/archive.html"), "")?;
+        f.config.templates.full_reload()?;
+        assert!(build(&f.config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn draft_and_hidden_collections() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = &f.config.toml.input;
+
+        for (dir, index) in [("d", "Draft: true"), ("h", "Hidden: true"), ("v", "")] {
+            create_dir(input.join(dir))?;
+            copy("data/test.jpg", input.join(dir).join(format!("{dir}.jpg")))?;
+            write(
+                input.join(dir).join("index.md"),
+                format!("Tags: all\n{index}"),
+            )?;
+        }
+
+        let templates = f.config.toml.theme.path.join("templates");
+        write(
+            templates.join("index.html"),
+            "{% for child in collection.children %}{{ child.path }} {% endfor %}",
+        )?;
+        write(
+            templates.join("tag.html"),
+            "{% for image in tag.images %}{{ image.path }} {% endfor %}",
+        )?;
+        f.config.templates.full_reload()?;
+
+        let output = f.config.toml.output.clone();
+        let collection = build(&f.config)?;
+        assert_eq!(read_to_string(output.join("index.html"))?, "v ");
+        assert_eq!(
+            read_to_string(output.join("tags/all/index.html"))?,
+            "../../v/v.jpg "
+        );
+        assert!(output.join("h/index.html").exists());
+        assert!(!output.join("d").exists());
+        assert_eq!(collection.thumbnail, input.join("v/v.jpg"));
+
+        f.config.drafts = true;
+        build(&f.config)?;
+        assert_eq!(read_to_string(output.join("index.html"))?, "v d ");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_metadata_of_children_fails() -> Result<()> {
+        let f = setup(None)?;
+        let input = &f.config.toml.input;
+        create_dir_all(input.join("a"))?;
+        copy("data/test.jpg", input.join("a.jpg"))?;
+        copy("data/test.jpg", input.join("a/b.jpg"))?;
+        write(
+            input.join("a/index.md"),
+            "+++\nmin_rating = \"three\"\n+++\n",
+        )?;
+
+        let err = f.collect().err().unwrap();
+        assert!(format!("{err:#}").contains("Invalid front matter"));
+        Ok(())
+    }
+
+    #[test]
+    fn only_hidden_children() -> Result<()> {
+        let f = setup(None)?;
+        let input = &f.config.toml.input;
+        create_dir_all(input.join("p/h"))?;
+        copy("data/test.jpg", input.join("p/h/a.jpg"))?;
+        write(input.join("p/h/index.md"), "Hidden: true")?;
+
+        build(&f.config)?;
+        let output = &f.config.toml.output;
+        assert!(output.join("p/index.html").exists());
+        assert!(output.join("p/h/index.html").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn archive_pages() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = &f.config.toml.input;
+        let subdir = input.join("s");
+        create_dir(&subdir)?;
+
+        let captured = |date: &str| ::exif::Field {
+            tag: ::exif::Tag::DateTimeOriginal,
+            ifd_num: ::exif::In::PRIMARY,
+            value: ::exif::Value::Ascii(vec![date.as_bytes().to_vec()]),
+        };
+
+        let jpeg = read("data/test.jpg")?;
+        write(
+            input.join("a.jpg"),
+            with_exif(&jpeg, &[captured("2025:06:14 12:00:00")])?,
+        )?;
+        write(
+            subdir.join("b.jpg"),
+            with_exif(&jpeg, &[captured("2025:06:01 08:00:00")])?,
+        )?;
+        write(
+            input.join("c.jpg"),
+            with_exif(&jpeg, &[captured("2025:07:01 08:00:00")])?,
+        )?;
+
+        // Without EXIF data the modification time is used.
+        let modified = Local.with_ymd_and_hms(2024, 12, 24, 18, 0, 0).unwrap();
+        copy("data/test.jpg", input.join("d.jpg"))?;
+        File::options()
+            .write(true)
+            .open(input.join("d.jpg"))?
+            .set_modified(modified.into())?;
+
+        write(
+            f.config.toml.theme.path.join("templates/archive.html"),
+            "{% if archive.year %}{{ archive.year }}{% endif %}\
+             {% if archive.month %}/{{ archive.month }}{% endif %}:\
+             {% for period in archive.periods %} {{ period.path }} {{ period.count }} {{ period.thumbnail }}{% endfor %}\
+             {% for image in archive.images %} {{ image.path }}{% endfor %}",
+        )?;
+        f.config.templates.full_reload()?;
+
+        let collection = build(&f.config)?;
+        let output = f.config.toml.output.join("archive");
+
+        assert_eq!(
+            read_to_string(output.join("index.html"))?,
+            ": 2024/ 1 ../thumbnails/d.jpg 2025/ 3 ../s/thumbnails/b.jpg"
+        );
+        assert_eq!(
+            read_to_string(output.join("2025/index.html"))?,
+            "2025: 06/ 2 ../../s/thumbnails/b.jpg 07/ 1 ../../thumbnails/c.jpg"
+        );
+        assert_eq!(
+            read_to_string(output.join("2025/06/index.html"))?,
+            "2025/06: ../../../s/b.jpg ../../../a.jpg"
+        );
+        assert_eq!(
+            read_to_string(output.join("2024/12/index.html"))?,
+            "2024/12: ../../../d.jpg"
+        );
+        assert!(prune::stale(&f.config, &collection)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn prune_stale_files() -> Result<()> {
+        let f = setup(None)?;
+        let subdir = f.config.toml.input.join("a");
+        create_dir(&subdir)?;
+        copy("data/test.jpg", f.config.toml.input.join("keep.jpg"))?;
+        copy("data/test.jpg", subdir.join("remove.jpg"))?;
+        build(&f.config)?;
+
+        std::fs::remove_dir_all(&subdir)?;
+        let collection = build(&f.config)?;
+
+        let output = &f.config.toml.output;
+        let stale = prune::stale(&f.config, &collection)?;
+        assert_eq!(stale.len(), 3);
+        assert!(stale.contains(&output.join("a/remove.jpg")));
+        assert!(stale.contains(&output.join("a/thumbnails/remove.jpg")));
+        assert!(stale.contains(&output.join("a/index.html")));
+
+        prune::prune(&f.config, &collection, true)?;
+        assert!(output.join("a/remove.jpg").exists());
+
+        prune::prune(&f.config, &collection, false)?;
+        assert!(!output.join("a").exists());
+        assert!(output.join("keep.jpg").exists());
+        assert!(output.join("thumbnails/keep.jpg").exists());
+        assert!(output.join("index.html").exists());
+        assert!(prune::stale(&f.config, &collection)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn prune_keeps_foreign_files() -> Result<()> {
+        let f = setup(None)?;
+        let output = &f.config.toml.output;
+        copy("data/test.jpg", f.config.toml.input.join("a.jpg"))?;
+        create_dir_all(output.join(".git/refs/tags"))?;
+        write(output.join(".git/HEAD"), "ref: refs/heads/gh-pages")?;
+        write(output.join("CNAME"), "photos.example.com")?;
+        create_dir_all(output.join("downloads/empty"))?;
+
+        let collection = build(&f.config)?;
+        assert!(prune::stale(&f.config, &collection)?.is_empty());
+
+        prune::prune(&f.config, &collection, false)?;
+        assert!(output.join(".git/HEAD").exists());
+        assert!(output.join(".git/refs/tags").exists());
+        assert!(output.join("CNAME").exists());
+        assert!(output.join("downloads/empty").exists());
+        assert!(output.join("a.jpg").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn process_rotated() -> Result<()> {
+        let f = setup(Some((900, 900)))?;
+        let orientation = ::exif::Field {
+            tag: ::exif::Tag::Orientation,
+            ifd_num: ::exif::In::PRIMARY,
+            value: ::exif::Value::Short(vec![6]),
+        };
+
+        // Tag test.jpg, which is 900x600 pixels, as rotated by 90 degrees.
+        write(
+            f.config.toml.input.join("test.jpg"),
+            with_exif(&read("data/test.jpg")?, &[orientation])?,
+        )?;
+
+        build(&f.config)?;
+        let collection = f.collect()?.unwrap();
+        let image = Image::new(&collection.items[0], &f.config)?;
+        assert_eq!((image.width, image.height), (600, 900));
+
+        let thumb_name = f.config.toml.output.join("thumbnails/test.jpg");
+        assert_eq!(dimensions(&thumb_name)?, (300, 200));
+
+        let resized_name = f.config.toml.output.join("test.jpg");
+        assert_eq!(dimensions(&resized_name)?, (600, 900));
+
+        Ok(())
+    }
+
     #[test]
     fn breadcrumb_links() -> Result<()> {
         let breadcrumbs = [
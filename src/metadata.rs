@@ -1,8 +1,11 @@
-use anyhow::Result;
+use crate::config::Sort;
+use anyhow::{Context, Result};
 use pulldown_cmark::{html, Parser};
 use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::{read_to_string, File};
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
@@ -15,6 +18,53 @@ pub struct Metadata {
     pub title: String,
     /// Override thumbnail image to use.
     pub thumbnail: Option<PathBuf>,
+    /// Override minimum rating of published images in this and all sub collections.
+    pub min_rating: Option<i8>,
+    /// Tags of all images in this collection.
+    pub tags: Vec<String>,
+    /// Override order of the images.
+    pub sort: Option<Sort>,
+    /// Override order of the child collections.
+    pub sort_children: Option<Sort>,
+    /// File names of images and directory names of child collections to put first in this order.
+    pub order: Vec<String>,
+    /// Skip this collection unless drafts are built.
+    pub draft: bool,
+    /// Build this collection but leave it out of listings.
+    pub hidden: bool,
+    /// Overrides of the settings inherited by this and all sub collections.
+    pub settings: Map<String, Value>,
+    /// All other keys passed through to templates.
+    pub extra: Map<String, Value>,
+}
+
+/// Keys of an `index.md` either given as typed front matter or legacy `Key: value` lines.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct FrontMatter {
+    title: Option<String>,
+    thumbnail: Option<PathBuf>,
+    min_rating: Option<i8>,
+    tags: Vec<String>,
+    sort: Option<Sort>,
+    sort_children: Option<Sort>,
+    order: Vec<String>,
+    draft: bool,
+    hidden: bool,
+    settings: Map<String, Value>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Caption read from a sidecar file of an image.
+#[derive(Default)]
+pub struct Caption {
+    /// Title of the image.
+    pub title: Option<String>,
+    /// Free text description.
+    pub description: String,
+    /// Tags of the image.
+    pub tags: Vec<String>,
 }
 
 static EXPRESSION: LazyLock<Regex> =
@@ -28,7 +78,19 @@ fn path_to_string(path: &Path) -> String {
         .to_owned()
 }
 
-fn from_str(path: &Path, content: &str) -> Result<Metadata> {
+/// Split a comma-separated value such as `Tags:`.
+fn list(value: Option<String>) -> Vec<String> {
+    value
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Split `content` into the leading `Key: value` lines and the rendered markdown description.
+fn parse(content: &str) -> (HashMap<String, String>, String) {
     let lines = content.lines();
     let mut matching_phase = true;
     let mut keys: HashMap<String, String> = HashMap::new();
@@ -48,21 +110,199 @@ fn from_str(path: &Path, content: &str) -> Result<Metadata> {
         description.push('\n');
     }
 
-    let parser = Parser::new(&description);
+    (keys, render(&description))
+}
+
+/// Render `markdown` to HTML.
+fn render(markdown: &str) -> String {
+    let parser = Parser::new(markdown);
     let mut html_output = String::new();
     html::push_html(&mut html_output, parser);
+    html_output
+}
+
+/// Convert a TOML value to JSON with dates and times as strings.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(value) => Value::String(value),
+        toml::Value::Integer(value) => Value::from(value),
+        toml::Value::Float(value) => Value::from(value),
+        toml::Value::Boolean(value) => Value::Bool(value),
+        toml::Value::Datetime(value) => Value::String(value.to_string()),
+        toml::Value::Array(values) => values.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Split a TOML front matter block delimited by `+++` or a YAML one delimited by `---` off the
+/// start of `content` and return it as JSON along with the remaining content. A `---` block that
+/// is not closed or not a YAML mapping is left to the legacy format.
+fn front_matter(path: &Path, content: &str) -> Result<Option<(Value, String)>> {
+    let Some((delimiter, rest)) = ["+++", "---"].into_iter().find_map(|delimiter| {
+        content
+            .strip_prefix(delimiter)
+            .and_then(|rest| rest.strip_prefix('\n').or(rest.strip_prefix("\r\n")))
+            .map(|rest| (delimiter, rest))
+    }) else {
+        return Ok(None);
+    };
+
+    let mut block = String::new();
+    let mut lines = rest.lines();
+
+    for line in lines.by_ref() {
+        if line.trim_end() == delimiter {
+            let rest = lines.map(|line| format!("{line}\n")).collect();
+            if delimiter == "---" {
+                // `---` also starts Markdown with a thematic break, so only take a YAML mapping.
+                return Ok(match serde_yaml::from_str::<Option<Value>>(&block) {
+                    Ok(None) => Some((Value::Object(Map::new()), rest)),
+                    Ok(Some(value @ Value::Object(_))) => Some((value, rest)),
+                    _ => None,
+                });
+            }
+
+            let value = toml::from_str(&block)
+                .map(toml_to_json)
+                .context(format!("Invalid front matter in {:?}", path))?;
+
+            return Ok(Some((value, rest)));
+        }
+
+        block.push_str(line);
+        block.push('\n');
+    }
+
+    Ok(None)
+}
 
-    let thumbnail = keys
-        .remove("Thumbnail")
-        .map(|s| path.join(PathBuf::from(s)))
+/// Remove the first fenced code block with the `order` info string from `content` and return its
+/// non-empty lines along with the remaining content.
+fn order_block(content: &str) -> (Vec<String>, String) {
+    let mut lines = content.lines();
+    let mut remaining = String::new();
+    let mut order = Vec::new();
+
+    while let Some(line) = lines.next() {
+        if line.trim() == "```order" {
+            order.extend(
+                lines
+                    .by_ref()
+                    .take_while(|line| line.trim() != "```")
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_owned),
+            );
+            remaining.extend(lines.map(|line| format!("{line}\n")));
+            break;
+        }
+
+        remaining.push_str(line);
+        remaining.push('\n');
+    }
+
+    (order, remaining)
+}
+
+/// Read the legacy `Key: value` lines at the start of `content` and return them along with the
+/// rendered description. Unknown keys are kept as strings.
+fn legacy(path: &Path, content: &str) -> Result<(FrontMatter, String)> {
+    let (mut keys, html_output) = parse(content);
+
+    let min_rating = keys
+        .remove("MinRating")
+        .map(|value| value.trim().parse())
+        .transpose()
+        .context(format!("MinRating in {:?} is not a number", path))?;
+
+    let sort = keys
+        .remove("Sort")
+        .map(|value| value.parse())
+        .transpose()
+        .context(format!("Invalid Sort in {:?}", path))?;
+
+    let sort_children = keys
+        .remove("SortChildren")
+        .map(|value| value.parse())
+        .transpose()
+        .context(format!("Invalid SortChildren in {:?}", path))?;
+
+    let draft = keys
+        .remove("Draft")
+        .map(|value| value.trim().parse())
+        .transpose()
+        .context(format!("Draft in {:?} is neither true nor false", path))?;
+
+    let hidden = keys
+        .remove("Hidden")
+        .map(|value| value.trim().parse())
+        .transpose()
+        .context(format!("Hidden in {:?} is neither true nor false", path))?;
+
+    let front_matter = FrontMatter {
+        title: keys.remove("Title"),
+        thumbnail: keys.remove("Thumbnail").map(PathBuf::from),
+        min_rating,
+        tags: list(keys.remove("Tags")),
+        sort,
+        sort_children,
+        order: list(keys.remove("Order")),
+        draft: draft.unwrap_or_default(),
+        hidden: hidden.unwrap_or_default(),
+        settings: Map::new(),
+        extra: keys
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect(),
+    };
+
+    Ok((front_matter, html_output))
+}
+
+fn from_str(path: &Path, content: &str) -> Result<Metadata> {
+    let (block, content) = order_block(content);
+
+    let (front_matter, html_output) = match front_matter(path, &content)? {
+        Some((value, body)) => (
+            FrontMatter::deserialize(value)
+                .context(format!("Invalid front matter in {:?}", path))?,
+            render(&body),
+        ),
+        None => legacy(path, &content)?,
+    };
+
+    let thumbnail = front_matter
+        .thumbnail
+        .map(|thumbnail| path.join(thumbnail))
         .filter(|path| path.exists());
 
-    let title = keys.remove("Title").unwrap_or_else(|| path_to_string(path));
+    let title = front_matter.title.unwrap_or_else(|| path_to_string(path));
+
+    let order = front_matter
+        .order
+        .into_iter()
+        .chain(block)
+        .map(|name| name.trim().trim_end_matches('/').to_owned())
+        .collect();
 
     Ok(Metadata {
         description: html_output,
         title,
         thumbnail,
+        min_rating: front_matter.min_rating,
+        tags: front_matter.tags,
+        sort: front_matter.sort,
+        sort_children: front_matter.sort_children,
+        order,
+        draft: front_matter.draft,
+        hidden: front_matter.hidden,
+        settings: front_matter.settings,
+        extra: front_matter.extra,
     })
 }
 
@@ -75,6 +315,15 @@ impl Metadata {
                 description: String::new(),
                 title: path_to_string(root),
                 thumbnail: None,
+                min_rating: None,
+                tags: vec![],
+                sort: None,
+                sort_children: None,
+                order: vec![],
+                draft: false,
+                hidden: false,
+                settings: Map::new(),
+                extra: Map::new(),
             });
         }
 
@@ -85,6 +334,38 @@ impl Metadata {
     }
 }
 
+/// Return the possible sidecar files of `image` with `extension` in order of precedence, e.g.
+/// `IMG_1234.jpg.md` and `IMG_1234.md`.
+pub fn sidecars(image: &Path, extension: &str) -> [PathBuf; 2] {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+
+    [path.into(), image.with_extension(extension)]
+}
+
+impl Caption {
+    /// Read the caption of `image` from its first existing markdown sidecar file. The `index.md`
+    /// of a collection is never used as a sidecar.
+    pub fn from_path(image: &Path) -> Result<Caption> {
+        let Some(sidecar) = sidecars(image, "md")
+            .into_iter()
+            .filter(|path| path.file_name().is_some_and(|name| name != "index.md"))
+            .find(|path| path.exists())
+        else {
+            return Ok(Caption::default());
+        };
+
+        let (mut keys, description) = parse(&read_to_string(&sidecar)?);
+
+        Ok(Caption {
+            title: keys.remove("Title"),
+            description,
+            tags: list(keys.remove("Tags")),
+        })
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -102,6 +383,118 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_min_rating() -> Result<()> {
+        let metadata = from_str(&PathBuf::from("."), "MinRating: 3\n\nGood ones.")?;
+        assert_eq!(metadata.min_rating, Some(3));
+        assert!(from_str(&PathBuf::from("."), "MinRating: many").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_sort() -> Result<()> {
+        let metadata = from_str(
+            &PathBuf::from("."),
+            "Sort: exif-date-desc\nSortChildren: natural",
+        )?;
+        assert_eq!(metadata.sort, Some(Sort::ExifDateDesc));
+        assert_eq!(metadata.sort_children, Some(Sort::Natural));
+        assert!(from_str(&PathBuf::from("."), "Sort: random").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_order() -> Result<()> {
+        let metadata = from_str(&PathBuf::from("."), "Order: c.jpg, sub/\n\nStory.")?;
+        assert_eq!(metadata.order, ["c.jpg", "sub"]);
+
+        let content = "Title: Story\n\nIntro.\n\n```order\nb.jpg\n\na.jpg\n```\n\nOutro.";
+        let metadata = from_str(&PathBuf::from("."), content)?;
+        assert_eq!(metadata.order, ["b.jpg", "a.jpg"]);
+        assert_eq!(metadata.description, "<p>Intro.</p>\n<p>Outro.</p>\n");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_toml_front_matter() -> Result<()> {
+        let content =
+            "+++\ntitle = \"Trip\"\nmin_rating = 2\ntags = [\"sea\"]\nsort = \"natural\"\n\
+                       date = 2024-05-01\nfeatured = true\n[map]\nzoom = 4\n+++\n\nGreat *trip*.";
+        let metadata = from_str(&PathBuf::from("."), content)?;
+        assert_eq!(metadata.title, "Trip");
+        assert_eq!(metadata.min_rating, Some(2));
+        assert_eq!(metadata.tags, ["sea"]);
+        assert_eq!(metadata.sort, Some(Sort::Natural));
+        assert_eq!(metadata.description, "<p>Great <em>trip</em>.</p>\n");
+        assert_eq!(
+            Value::Object(metadata.extra),
+            serde_json::json!({"date": "2024-05-01", "featured": true, "map": {"zoom": 4}})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_yaml_front_matter() -> Result<()> {
+        let content = "---\ntitle: Trip\norder: [b.jpg, a.jpg]\nlinks:\n  - one\n---\nText.";
+        let metadata = from_str(&PathBuf::from("."), content)?;
+        assert_eq!(metadata.title, "Trip");
+        assert_eq!(metadata.order, ["b.jpg", "a.jpg"]);
+        assert_eq!(metadata.description, "<p>Text.</p>\n");
+        assert_eq!(metadata.extra["links"], serde_json::json!(["one"]));
+
+        assert!(from_str(&PathBuf::from("."), "---\nmin_rating: many\n---\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn thematic_break_is_not_front_matter() -> Result<()> {
+        let content = "---\n\nSome *text*.\n\n---\n\nMore.";
+        let metadata = from_str(&PathBuf::from("."), content)?;
+        assert_eq!(
+            metadata.description,
+            "<hr />\n<p>Some <em>text</em>.</p>\n<hr />\n<p>More.</p>\n"
+        );
+
+        let metadata = from_str(&PathBuf::from("."), "---\nUnclosed.")?;
+        assert_eq!(metadata.description, "<hr />\n<p>Unclosed.</p>\n");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_flags() -> Result<()> {
+        let metadata = from_str(&PathBuf::from("."), "Draft: true\nHidden: false")?;
+        assert!(metadata.draft && !metadata.hidden);
+        let metadata = from_str(&PathBuf::from("."), "+++\nhidden = true\n+++\n")?;
+        assert!(!metadata.draft && metadata.hidden);
+        assert!(from_str(&PathBuf::from("."), "Draft: maybe").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_keys_are_extra() -> Result<()> {
+        let metadata = from_str(&PathBuf::from("."), "Title: foo\nLocation: Lisbon\n\nText.")?;
+        assert_eq!(metadata.extra["Location"], "Lisbon");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_tags() -> Result<()> {
+        let metadata = from_str(&PathBuf::from("."), "Tags: sea, boats,, Dawn\n\nHarbour.")?;
+        assert_eq!(metadata.tags, ["sea", "boats", "Dawn"]);
+        Ok(())
+    }
+
+    #[test]
+    fn sidecar_paths() {
+        assert_eq!(
+            sidecars(Path::new("a/IMG_1234.jpg"), "md"),
+            [
+                PathBuf::from("a/IMG_1234.jpg.md"),
+                PathBuf::from("a/IMG_1234.md")
+            ]
+        );
+    }
+
     #[test]
     fn no_metadata_is_description() -> Result<()> {
         let metadata = from_str(&PathBuf::from("."), "This is *bold*.")?;
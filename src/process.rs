@@ -1,70 +1,351 @@
-use crate::config;
+use crate::config::{self, Codec, Encoding, Format, Jpeg, Mode, Subsampling};
 use crate::Item;
 use anyhow::{anyhow, Context, Result};
-use image::{imageops, ImageReader};
-use std::fs::{copy, create_dir_all};
-use std::path::Path;
+use image::codecs::avif::AvifEncoder;
+use image::metadata::Orientation;
+use image::{imageops, DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader};
+use serde::Serialize;
+use std::fs::{copy, create_dir_all, write, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 
 pub struct Process<'a> {
-    pub config: &'a config::Config,
     pub item: &'a Item,
+    /// Outdated targets of `item`.
+    pub targets: Vec<Target<'a>>,
     pub sender: Sender<Result<()>>,
 }
 
-fn resize(source: &Path, dest: &Path, width: u32, height: u32) -> Result<()> {
-    let image = ImageReader::open(source)?
-        .decode()
+/// Detect the format of `path` by its extension or, failing that, by its magic bytes.
+pub fn detect_format(path: &Path) -> Option<Format> {
+    let format = ImageFormat::from_path(path).ok().or_else(|| {
+        ImageReader::open(path)
+            .ok()?
+            .with_guessed_format()
+            .ok()?
+            .format()
+    })?;
+
+    Format::from_image_format(format)
+}
+
+/// Decode `source` and rotate or flip it according to its EXIF orientation.
+fn open(source: &Path) -> Result<DynamicImage> {
+    let mut decoder = ImageReader::open(source)?
+        .with_guessed_format()?
+        .into_decoder()
         .context(format!("{:?} does not seem to be a valid image", source))?;
-    let resized = image.resize_to_fill(width, height, imageops::FilterType::Lanczos3);
-    Ok(resized.save(dest)?)
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Return the dimensions of `path` as displayed by a browser, i.e. swapped if the EXIF orientation
+/// rotates the image by 90 or 270 degrees.
+pub fn dimensions(path: &Path) -> Result<(u32, u32)> {
+    let mut decoder = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    let (width, height) = decoder.dimensions();
+
+    Ok(match decoder.orientation()? {
+        Orientation::Rotate90
+        | Orientation::Rotate270
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270FlipH => (height, width),
+        _ => (width, height),
+    })
+}
+
+/// Compute the size an image of `size` is scaled to for `mode` and the `bounds` from the
+/// configuration. The result is never larger than `size`.
+fn scaled_size(mode: Mode, size: (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let (width, height) = (f64::from(size.0), f64::from(size.1));
+    let (max_width, max_height) = (f64::from(bounds.0), f64::from(bounds.1));
+
+    let scale = match mode {
+        Mode::Fit => (max_width / width).min(max_height / height),
+        Mode::Fill => (max_width / width).max(max_height / height),
+        Mode::Width => max_width / width,
+        Mode::Height => max_height / height,
+        Mode::LongestEdge => max_width.max(max_height) / width.max(height),
+    }
+    .min(1.0);
+
+    // Scaled sizes are at most as large as the original and thus fit into u32.
+    let scaled = |length: f64| ((length * scale).round() as u32).max(1);
+
+    (scaled(width), scaled(height))
+}
+
+/// Compute the final size of an image of `size` scaled into `bounds` according to `mode`.
+pub fn output_size(mode: Mode, size: (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let (width, height) = scaled_size(mode, size, bounds);
+
+    if mode == Mode::Fill {
+        (width.min(bounds.0), height.min(bounds.1))
+    } else {
+        (width, height)
+    }
+}
+
+/// Scale `image` into `width` x `height` according to `mode`.
+fn scale(image: &DynamicImage, width: u32, height: u32, mode: Mode) -> DynamicImage {
+    let (scaled_width, scaled_height) = scaled_size(mode, image.dimensions(), (width, height));
+
+    let scaled = if (scaled_width, scaled_height) == image.dimensions() {
+        image.clone()
+    } else {
+        image.resize_exact(scaled_width, scaled_height, imageops::FilterType::Lanczos3)
+    };
+
+    if mode != Mode::Fill {
+        return scaled;
+    }
+
+    // Crop the center if the scaled image exceeds the bounds.
+    let (crop_width, crop_height) = (scaled_width.min(width), scaled_height.min(height));
+
+    scaled.crop_imm(
+        (scaled_width - crop_width) / 2,
+        (scaled_height - crop_height) / 2,
+        crop_width,
+        crop_height,
+    )
+}
+
+/// Encode `image` as JPEG into `dest` using the `jpeg` encoder settings.
+fn save_jpeg(image: &DynamicImage, dest: &Path, jpeg: &Jpeg) -> Result<()> {
+    let mut encoder = jpeg_encoder::Encoder::new_file(dest, jpeg.quality)?;
+    encoder.set_progressive(jpeg.progressive);
+    encoder.set_sampling_factor(match jpeg.subsampling {
+        Subsampling::Yuv444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+        Subsampling::Yuv422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+        Subsampling::Yuv420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+
+    let width = u16::try_from(image.width()).context("Image too wide for JPEG")?;
+    let height = u16::try_from(image.height()).context("Image too high for JPEG")?;
+
+    match image {
+        DynamicImage::ImageLuma8(luma) => {
+            encoder.encode(luma, width, height, jpeg_encoder::ColorType::Luma)
+        }
+        _ => encoder.encode(
+            &image.to_rgb8(),
+            width,
+            height,
+            jpeg_encoder::ColorType::Rgb,
+        ),
+    }?;
+
+    Ok(())
+}
+
+/// Encode `image` as lossy WebP with `quality` between 1 and 100 into `dest`.
+fn save_webp(image: &DynamicImage, dest: &Path, quality: u8) -> Result<()> {
+    let rgba = image.to_rgba8();
+    let memory = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+        .encode_simple(false, f32::from(quality))
+        .map_err(|err| anyhow!("Failed to encode {:?} as WebP: {:?}", dest, err))?;
+
+    Ok(write(dest, &*memory)?)
+}
+
+/// Encode `image` as `format` into `dest`. JPEG and WebP output use the `jpeg` encoder settings
+/// where they apply.
+fn save(image: &DynamicImage, dest: &Path, format: ImageFormat, jpeg: &Jpeg) -> Result<()> {
+    match format {
+        ImageFormat::Jpeg => save_jpeg(image, dest, jpeg),
+        ImageFormat::WebP => save_webp(image, dest, jpeg.quality),
+        _ => Ok(image.save_with_format(dest, format)?),
+    }
+    .context(format!("Failed to write {:?}", dest))
+}
+
+/// Return the path of `path` additionally encoded as `codec`, i.e. `foo.jpg` becomes
+/// `foo.jpg.webp`.
+pub fn encoded_path(path: &Path, codec: Codec) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(codec.extension());
+    PathBuf::from(path)
+}
+
+/// Store `image` in all additional `encodings` next to `dest`.
+fn encode(image: &DynamicImage, dest: &Path, encodings: &[Encoding]) -> Result<()> {
+    for encoding in encodings {
+        let path = encoded_path(dest, encoding.format);
+
+        match encoding.format {
+            Codec::Webp => save_webp(image, &path, encoding.quality)?,
+            Codec::Avif => {
+                let writer = BufWriter::new(File::create(&path)?);
+                image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                    writer,
+                    8,
+                    encoding.quality,
+                ))?;
+            }
+        }
+    }
+
+    Ok(())
 }
 
 pub fn is_older(first: &Path, second: &Path) -> Result<bool> {
     Ok(first.metadata()?.modified()? < second.metadata()?.modified()?)
 }
 
-fn generate_thumbnail(p: &Process) -> Result<()> {
-    let thumb_dir = p.item.thumbnail.parent();
+/// How a derived image is generated from the source image.
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Copy the source as is.
+    Copy,
+    /// Decode, scale and encode the source.
+    Scale {
+        width: u32,
+        height: u32,
+        mode: Mode,
+        format: Format,
+        encodings: Vec<Encoding>,
+        jpeg: Jpeg,
+    },
+}
+
+/// A derived image of an [`Item`].
+pub struct Target<'a> {
+    /// Path of the derived image.
+    pub dest: &'a Path,
+    pub operation: Operation,
+}
+
+impl Target<'_> {
+    /// Additional encodings stored next to `dest`.
+    pub fn encodings(&self) -> &[Encoding] {
+        match &self.operation {
+            Operation::Copy => &[],
+            Operation::Scale { encodings, .. } => encodings,
+        }
+    }
+
+    fn generate(&self, source: &Path, image: Option<&DynamicImage>) -> Result<()> {
+        if let Some(dir) = self.dest.parent() {
+            if !dir.exists() {
+                create_dir_all(dir)?;
+            }
+        }
 
-    if let Some(dir) = thumb_dir {
-        if !dir.exists() {
-            create_dir_all(dir)?;
+        match (&self.operation, image) {
+            (
+                Operation::Scale {
+                    width,
+                    height,
+                    mode,
+                    format,
+                    encodings,
+                    jpeg,
+                },
+                Some(image),
+            ) => {
+                let image = scale(image, *width, *height, *mode);
+                save(&image, self.dest, format.output(), jpeg)?;
+                encode(&image, self.dest, encodings).context(format!("Processing {:?}", source))
+            }
+            _ => copy(source, self.dest)
+                .context(format!("Copying {:?} => {:?}", source, self.dest))
+                .map(|_| ()),
         }
     }
+}
+
+/// Return all images derived from `item` according to the configuration and the settings of its
+/// collection.
+pub fn targets<'a>(item: &'a Item, config: &config::Config) -> Vec<Target<'a>> {
+    let toml = &config.toml;
+    let settings = &item.settings;
+
+    let mut targets = vec![Target {
+        dest: &item.thumbnail,
+        operation: Operation::Scale {
+            width: settings.thumbnail.width,
+            height: settings.thumbnail.height,
+            mode: settings.thumbnail.mode,
+            format: item.format,
+            encodings: settings.thumbnail.encodings.clone(),
+            jpeg: settings.thumbnail.jpeg.clone(),
+        },
+    }];
 
-    if !p.item.thumbnail.exists() || p.item.thumbnail_outdated()? {
-        resize(
-            &p.item.from,
-            &p.item.thumbnail,
-            p.config.toml.thumbnail.width,
-            p.config.toml.thumbnail.height,
-        )?;
+    for (size, path) in toml.sizes.iter().zip(&item.sizes) {
+        targets.push(Target {
+            dest: path,
+            operation: Operation::Scale {
+                width: size.width,
+                height: 0,
+                mode: Mode::Width,
+                format: item.format,
+                encodings: size.encodings.clone(),
+                jpeg: size.jpeg.clone(),
+            },
+        });
     }
 
-    Ok(())
+    let operation = match &settings.resize {
+        Some(resize) => Operation::Scale {
+            width: resize.width,
+            height: resize.height,
+            mode: resize.mode,
+            format: item.format,
+            encodings: resize.encodings.clone(),
+            jpeg: resize.jpeg.clone(),
+        },
+        // Convert without scaling.
+        None if !item.format.is_web_compatible() => Operation::Scale {
+            width: u32::MAX,
+            height: u32::MAX,
+            mode: Mode::Fit,
+            format: item.format,
+            encodings: vec![],
+            jpeg: Jpeg::default(),
+        },
+        None => Operation::Copy,
+    };
+
+    targets.push(Target {
+        dest: &item.to,
+        operation,
+    });
+
+    targets
 }
 
 fn wrapped_process(p: &Process) -> Result<()> {
-    generate_thumbnail(p)?;
+    let needs_decode = p
+        .targets
+        .iter()
+        .any(|target| matches!(target.operation, Operation::Scale { .. }));
 
-    if p.item.to.exists() && is_older(&p.item.to, &p.item.from)? {
-        return Ok(());
-    }
+    // Decode only once for all derived images.
+    let image = needs_decode.then(|| open(&p.item.from)).transpose()?;
 
-    match &p.config.toml.resize {
-        Some(target) => resize(&p.item.from, &p.item.to, target.width, target.height),
-        None => copy(&p.item.from, &p.item.to)
-            .context(format!("Copying {:?} => {:?}", p.item.from, p.item.to))
-            .map(|_| ()),
-    }?;
+    for target in &p.targets {
+        target.generate(&p.item.from, image.as_ref())?;
+    }
 
     Ok(())
 }
 
-pub fn process(p: &Process) -> Result<()> {
-    p.sender.send(wrapped_process(p))?;
-    Ok(())
+/// Generate all outdated targets of `p` and report the outcome to the progress display. Returns
+/// `true` if processing was successful.
+pub fn process(p: &Process) -> Result<bool> {
+    let result = wrapped_process(p);
+    let success = result.is_ok();
+    p.sender.send(result)?;
+    Ok(success)
 }
 
 fn do_copy(path: &Path, prefix: &Path, output: &Path) -> Result<()> {
@@ -87,3 +368,66 @@ pub fn copy_recursively(path: &Path, output: &Path) -> Result<()> {
     let prefix = path.parent().ok_or_else(|| anyhow!("No parent"))?;
     do_copy(path, prefix, output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_sizes() {
+        assert_eq!(scaled_size(Mode::Fit, (900, 600), (300, 300)), (300, 200));
+        assert_eq!(scaled_size(Mode::Fill, (900, 600), (300, 300)), (450, 300));
+        assert_eq!(scaled_size(Mode::Width, (600, 900), (300, 100)), (300, 450));
+        assert_eq!(
+            scaled_size(Mode::Height, (900, 600), (100, 300)),
+            (450, 300)
+        );
+        assert_eq!(
+            scaled_size(Mode::LongestEdge, (600, 900), (450, 300)),
+            (300, 450)
+        );
+    }
+
+    #[test]
+    fn never_upscale() {
+        for mode in [
+            Mode::Fit,
+            Mode::Fill,
+            Mode::Width,
+            Mode::Height,
+            Mode::LongestEdge,
+        ] {
+            assert_eq!(scaled_size(mode, (900, 600), (1800, 1800)), (900, 600));
+        }
+    }
+
+    #[test]
+    fn output_sizes() {
+        assert_eq!(output_size(Mode::Fill, (900, 600), (300, 300)), (300, 300));
+        assert_eq!(output_size(Mode::Fill, (900, 600), (1800, 300)), (900, 300));
+        assert_eq!(output_size(Mode::Fit, (900, 600), (300, 300)), (300, 200));
+    }
+
+    #[test]
+    fn oversized_webp_fails() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let image = DynamicImage::new_rgb8(1, 17000);
+        let encodings = [Encoding {
+            format: Codec::Webp,
+            quality: 80,
+        }];
+
+        assert!(encode(&image, &dir.path().join("a.jpg"), &encodings).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fill_crops_to_bounds() {
+        let image = DynamicImage::new_rgb8(900, 600);
+        assert_eq!(scale(&image, 300, 300, Mode::Fill).dimensions(), (300, 300));
+        assert_eq!(
+            scale(&image, 1800, 300, Mode::Fill).dimensions(),
+            (900, 300)
+        );
+    }
+}
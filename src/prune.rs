@@ -0,0 +1,149 @@
+use crate::cache::{Cache, CACHE_FILENAME};
+use crate::config::Config;
+use crate::process::{encoded_path, targets};
+use crate::{archive, tags, Collection};
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::fs::{read_dir, remove_dir, remove_file};
+use std::path::{Path, PathBuf};
+
+/// Add all files below `path` to `files`.
+fn walk(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in read_dir(path)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Add pages and derived images of `collection` and all its children to `files`.
+fn add_collection(
+    config: &Config,
+    collection: &Collection,
+    files: &mut BTreeSet<PathBuf>,
+) -> Result<()> {
+    let output = config
+        .toml
+        .output
+        .join(collection.path.strip_prefix(&config.toml.input)?);
+
+    files.insert(output.join("index.html"));
+
+    for item in &collection.items {
+        if config.has_template("image.html") {
+            files.insert(item.page());
+        }
+
+        for target in targets(item, config) {
+            for encoding in target.encodings() {
+                files.insert(encoded_path(target.dest, encoding.format));
+            }
+
+            files.insert(target.dest.to_owned());
+        }
+    }
+
+    for child in &collection.collections {
+        add_collection(config, child, files)?;
+    }
+
+    Ok(())
+}
+
+/// Return all files a build of `collection` writes into the output directory.
+pub fn produced(config: &Config, collection: &Collection) -> Result<BTreeSet<PathBuf>> {
+    let output = &config.toml.output;
+    let mut files = BTreeSet::from([output.join(CACHE_FILENAME)]);
+
+    add_collection(config, collection, &mut files)?;
+    files.extend(tags::pages(config, collection));
+    files.extend(archive::pages(config, collection));
+
+    if let Some(static_path) = config.static_path.as_ref() {
+        let mut static_files = Vec::new();
+        walk(static_path, &mut static_files)?;
+
+        if let Some(prefix) = static_path.parent() {
+            for path in static_files {
+                files.insert(output.join(path.strip_prefix(prefix)?));
+            }
+        }
+    }
+
+    if let Some(processes) = &config.toml.theme.process {
+        files.extend(processes.iter().map(|process| process.output().to_owned()));
+    }
+
+    Ok(files)
+}
+
+/// Record the files produced by building `collection` in the cache of the output directory.
+pub fn record(config: &Config, collection: &Collection) -> Result<()> {
+    let output = &config.toml.output;
+    let mut cache = Cache::load(output);
+    cache.record(&produced(config, collection)?, output, false);
+    cache.save(output)
+}
+
+/// Return files written by previous builds that are not produced by building `collection`. Files
+/// in the output directory that splat did not write are never stale.
+pub fn stale(config: &Config, collection: &Collection) -> Result<Vec<PathBuf>> {
+    let produced = produced(config, collection)?;
+
+    Ok(Cache::load(&config.toml.output)
+        .files(&config.toml.output)
+        .into_iter()
+        .filter(|path| path.is_file() && !produced.contains(path))
+        .collect())
+}
+
+/// Remove the directories below `output` containing the removed file at `path` as long as they
+/// became empty. Directories splat did not empty are left alone.
+fn remove_empty_parents(path: &Path, output: &Path) -> Result<()> {
+    for dir in path.ancestors().skip(1) {
+        if dir == output || !dir.starts_with(output) || read_dir(dir)?.next().is_some() {
+            break;
+        }
+
+        remove_dir(dir)?;
+    }
+
+    Ok(())
+}
+
+/// Remove stale files from the output directory or only list them if `dry_run` is set.
+pub fn prune(config: &Config, collection: &Collection, dry_run: bool) -> Result<()> {
+    let produced = produced(config, collection)?;
+    let stale = stale(config, collection)?;
+
+    for path in &stale {
+        if dry_run {
+            println!("  Would remove {}", path.display());
+        } else {
+            remove_file(path)?;
+            remove_empty_parents(path, &config.toml.output)?;
+        }
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    let output = &config.toml.output;
+    let mut cache = Cache::load(output);
+    cache.record(&produced, output, true);
+    cache.save(output)?;
+
+    println!(
+        "\x1B[2K\r\x1B[0;32m✔\x1B[0;m Removed {} stale files",
+        stale.len()
+    );
+
+    Ok(())
+}
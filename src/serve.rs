@@ -0,0 +1,193 @@
+use crate::config::Config;
+use crate::watch::{watch, Gallery};
+use anyhow::{anyhow, Result};
+use percent_encoding::percent_decode_str;
+use std::fs::{read_to_string, File};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Request, Response, Server};
+
+/// Path of the server-sent events stream notifying browsers about rebuilds.
+const RELOAD_PATH: &str = "/__splat/reload";
+
+/// Script injected into served HTML pages to reload them after a rebuild.
+static RELOAD_SCRIPT: &str =
+    r#"<script>new EventSource("/__splat/reload").onmessage = () => location.reload();</script>"#;
+
+/// Open event streams of connected browsers.
+type Clients = Arc<Mutex<Vec<Box<dyn Write + Send>>>>;
+
+fn content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Map the request `url` to a path below `root`. Returns `None` for paths escaping `root`.
+fn resolve(root: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let path = Path::new(path.trim_start_matches('/'));
+
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        .then(|| root.join(path))
+}
+
+/// Insert the reload script right before the closing body tag or append it.
+fn inject_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(index) => format!("{}{RELOAD_SCRIPT}{}", &html[..index], &html[index..]),
+        None => format!("{html}{RELOAD_SCRIPT}"),
+    }
+}
+
+fn header(field: &str, value: &str) -> Result<Header> {
+    Header::from_bytes(field, value).map_err(|_| anyhow!("Invalid header {field}: {value}"))
+}
+
+fn handle(request: Request, root: &Path, clients: &Clients) -> Result<()> {
+    let url = request.url().to_owned();
+
+    if url == RELOAD_PATH {
+        let mut writer = request.into_writer();
+        writer.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+        )?;
+        writer.flush()?;
+
+        clients
+            .lock()
+            .map_err(|_| anyhow!("Poisoned client list"))?
+            .push(writer);
+
+        return Ok(());
+    }
+
+    let Some(mut path) = resolve(root, &url) else {
+        return Ok(request.respond(Response::empty(403))?);
+    };
+
+    if path.is_dir() {
+        // Relative links in pages only work with a trailing slash.
+        if !url.ends_with('/') {
+            let response =
+                Response::empty(301).with_header(header("Location", &format!("{url}/"))?);
+            return Ok(request.respond(response)?);
+        }
+
+        path = path.join("index.html");
+    }
+
+    if !path.is_file() {
+        return Ok(request.respond(Response::from_string("Not found").with_status_code(404))?);
+    }
+
+    let content_type = header("Content-Type", content_type(&path))?;
+
+    if path.extension().is_some_and(|ext| ext == "html") {
+        let html = inject_reload(&read_to_string(&path)?);
+        Ok(request.respond(Response::from_string(html).with_header(content_type))?)
+    } else {
+        Ok(request.respond(Response::from_file(File::open(&path)?).with_header(content_type))?)
+    }
+}
+
+/// Tell all connected browsers to reload and forget disconnected ones.
+fn reload(clients: &Clients) -> Result<()> {
+    let mut clients = clients
+        .lock()
+        .map_err(|_| anyhow!("Poisoned client list"))?;
+
+    clients.retain_mut(|client| {
+        client
+            .write_all(b"data: reload\n\n")
+            .and_then(|_| client.flush())
+            .is_ok()
+    });
+
+    Ok(())
+}
+
+/// Build the gallery, serve the output on `port` and rebuild and reload browsers on changes.
+pub fn serve(port: u16) -> Result<()> {
+    let mut gallery = Gallery::new(Config::read()?)?;
+
+    let server = Server::http(("127.0.0.1", port)).map_err(|err| anyhow!("{err}"))?;
+    let clients = Clients::default();
+    // The output directory may change with the configuration.
+    let root = Arc::new(Mutex::new(gallery.config().toml.output.clone()));
+
+    {
+        let root = root.clone();
+        let clients = clients.clone();
+
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let root = match root.lock() {
+                    Ok(root) => root.clone(),
+                    Err(_) => {
+                        eprintln!("failed to handle request: Poisoned output path");
+                        continue;
+                    }
+                };
+
+                if let Err(err) = handle(request, &root, &clients) {
+                    eprintln!("failed to handle request: {err:?}");
+                }
+            }
+        });
+    }
+
+    println!("\x1B[2K\r\x1B[0;32m✔\x1B[0;m Serving on http://127.0.0.1:{port}");
+
+    watch(&mut gallery, |gallery| {
+        *root.lock().map_err(|_| anyhow!("Poisoned output path"))? =
+            gallery.config().toml.output.clone();
+        reload(&clients)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_paths() {
+        let root = Path::new("output");
+        assert_eq!(resolve(root, "/"), Some(PathBuf::from("output/")));
+        assert_eq!(
+            resolve(root, "/a%20b/c.jpg?v=1"),
+            Some(PathBuf::from("output/a b/c.jpg"))
+        );
+        assert_eq!(resolve(root, "/../splat.toml"), None);
+        assert_eq!(resolve(root, "/a/%2E%2E/%2E%2E/splat.toml"), None);
+    }
+
+    #[test]
+    fn inject_reload_script() {
+        assert_eq!(
+            inject_reload("<body></body>"),
+            format!("<body>{RELOAD_SCRIPT}</body>")
+        );
+        assert_eq!(inject_reload("<p>"), format!("<p>{RELOAD_SCRIPT}"));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::config::Sort;
+use crate::{Collection, Item};
+use chrono::NaiveDateTime;
+use std::cmp::{Ordering, Reverse};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Values ordered by a [`Sort`].
+pub trait Sortable {
+    /// Name compared by [`Sort::Name`], [`Sort::NameDesc`] and [`Sort::Natural`].
+    fn name(&self) -> String;
+    /// File or directory name matched against the `Order:` of a collection.
+    fn file(&self) -> String;
+    /// Date compared by [`Sort::Date`].
+    fn date(&self) -> Option<NaiveDateTime>;
+    /// EXIF capture date compared by [`Sort::ExifDateDesc`].
+    fn exif_date(&self) -> Option<NaiveDateTime>;
+}
+
+impl Sortable for &Item {
+    fn name(&self) -> String {
+        self.to
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn file(&self) -> String {
+        self.from
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn date(&self) -> Option<NaiveDateTime> {
+        self.date
+    }
+
+    fn exif_date(&self) -> Option<NaiveDateTime> {
+        self.exif
+            .date
+            .as_deref()
+            .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").ok())
+    }
+}
+
+/// Collections are named by their title and dated by their oldest and newest items.
+impl Sortable for &Collection {
+    fn name(&self) -> String {
+        self.metadata.title.clone()
+    }
+
+    fn file(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn date(&self) -> Option<NaiveDateTime> {
+        self.items().iter().filter_map(|item| item.date).min()
+    }
+
+    fn exif_date(&self) -> Option<NaiveDateTime> {
+        self.items()
+            .iter()
+            .filter_map(|item| item.exif_date())
+            .max()
+    }
+}
+
+/// Consume the run of digits at the start of `chars`.
+fn number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        number.push(digit);
+    }
+
+    number
+}
+
+/// Compare `a` and `b` with runs of digits compared by their numeric value.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = number(&mut a);
+                let y = number(&mut b);
+                let (x_value, y_value) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+
+                // Equal values with more leading zeros come last.
+                x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value))
+                    .then_with(|| x.len().cmp(&y.len()))
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(y);
+                a.next();
+                b.next();
+                ordering
+            }
+        };
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+}
+
+/// Order `values` according to `sort`. Ties are broken by name, [`Sort::Manual`] orders by file
+/// name until [`order`] is applied.
+pub fn sort<T: Sortable>(values: &mut [T], sort: Sort) {
+    match sort {
+        Sort::Date => values.sort_by_cached_key(|v| (v.date().is_none(), v.date(), v.name())),
+        Sort::Name => values.sort_by_cached_key(|v| v.name()),
+        Sort::NameDesc => values.sort_by_cached_key(|v| Reverse(v.name())),
+        Sort::Natural => values.sort_by(|a, b| natural_cmp(&a.name(), &b.name())),
+        Sort::ExifDateDesc => values.sort_by_cached_key(|v| (Reverse(v.exif_date()), v.name())),
+        Sort::Manual => values.sort_by_cached_key(|v| v.file()),
+    }
+}
+
+/// Move the values whose file is listed in `order` to the front in the listed order, keeping the
+/// order of the remaining values.
+pub fn order<T: Sortable>(values: &mut [T], order: &[String]) {
+    values.sort_by_cached_key(|value| {
+        let file = value.file();
+        order
+            .iter()
+            .position(|name| *name == file)
+            .unwrap_or(order.len())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    struct Value(&'static str, Option<u32>);
+
+    impl Sortable for Value {
+        fn name(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn file(&self) -> String {
+            format!("{}.jpg", self.0)
+        }
+
+        fn date(&self) -> Option<NaiveDateTime> {
+            self.1
+                .and_then(|day| NaiveDate::from_ymd_opt(2024, 1, day))
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        }
+
+        fn exif_date(&self) -> Option<NaiveDateTime> {
+            self.date()
+        }
+    }
+
+    fn sorted(order: Sort) -> Vec<&'static str> {
+        let mut values = vec![
+            Value("b10", Some(2)),
+            Value("b2", None),
+            Value("a", Some(3)),
+            Value("c", Some(1)),
+        ];
+        sort(&mut values, order);
+        values.into_iter().map(|value| value.0).collect()
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "img10.jpg",
+            "img2.jpg",
+            "IMG1.jpg",
+            "img02.jpg",
+            "img1b.jpg",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            [
+                "IMG1.jpg",
+                "img1b.jpg",
+                "img2.jpg",
+                "img02.jpg",
+                "img10.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_orders() {
+        assert_eq!(sorted(Sort::Name), ["a", "b10", "b2", "c"]);
+        assert_eq!(sorted(Sort::NameDesc), ["c", "b2", "b10", "a"]);
+        assert_eq!(sorted(Sort::Natural), ["a", "b2", "b10", "c"]);
+        assert_eq!(sorted(Sort::Date), ["c", "b10", "a", "b2"]);
+        assert_eq!(sorted(Sort::ExifDateDesc), ["a", "b10", "c", "b2"]);
+        assert_eq!(sorted(Sort::Manual), ["a", "b10", "b2", "c"]);
+    }
+
+    #[test]
+    fn listed_order() {
+        let mut values = vec![Value("a", None), Value("b", None), Value("c", None)];
+        order(&mut values, &["c.jpg".to_owned(), "missing.jpg".to_owned()]);
+        let names = values.iter().map(|value| value.0).collect::<Vec<_>>();
+        assert_eq!(names, ["c", "a", "b"]);
+    }
+}
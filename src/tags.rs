@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::{relative, Collection, Image, Item, Summary};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use slug::slugify;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, write};
+use std::path::PathBuf;
+
+/// Items sharing a tag.
+struct Tag<'a> {
+    /// Name of the tag as first seen.
+    name: &'a str,
+    /// Items with the tag.
+    items: Vec<&'a Item>,
+}
+
+/// Context of a tag page.
+#[derive(Serialize)]
+struct Page<'a> {
+    /// Name of the tag.
+    name: &'a str,
+    /// Images with the tag.
+    images: Vec<Image<'a>>,
+}
+
+/// Group all items of `collection` and its children by the slugs of their tags. Tags without a
+/// slug such as `!!!` are skipped.
+fn collect(collection: &Collection) -> BTreeMap<String, Tag<'_>> {
+    let mut items = collection.listed();
+    items.sort_by(|a, b| a.to.cmp(&b.to));
+
+    let mut tags = BTreeMap::new();
+
+    for item in items {
+        for name in &item.tags {
+            let slug = slugify(name);
+
+            if slug.is_empty() {
+                continue;
+            }
+
+            tags.entry(slug)
+                .or_insert_with(|| Tag {
+                    name,
+                    items: vec![],
+                })
+                .items
+                .push(item);
+        }
+    }
+
+    tags
+}
+
+/// Fail if the tag pages would overwrite the pages of a collection named `tags`.
+pub fn check(config: &Config, collection: &Collection) -> Result<()> {
+    let path = config.toml.input.join("tags");
+    let writes = config.has_template("tag.html") || config.has_template("tags.html");
+
+    if writes && collection.find(&path).is_some() {
+        return Err(anyhow!(
+            "Collection {:?} collides with the tag pages, rename it or remove tag.html and tags.html",
+            path
+        ));
+    }
+
+    Ok(())
+}
+
+/// Return the pages written for the tags of `collection`.
+pub fn pages(config: &Config, collection: &Collection) -> Vec<PathBuf> {
+    let root = config.toml.output.join("tags");
+    let mut pages = Vec::new();
+
+    if config.has_template("tag.html") {
+        pages.extend(
+            collect(collection)
+                .into_keys()
+                .map(|slug| root.join(slug).join("index.html")),
+        );
+    }
+
+    if config.has_template("tags.html") {
+        pages.push(root.join("index.html"));
+    }
+
+    pages
+}
+
+/// Write a page for each tag with the `tag.html` template and an overview of all tags with the
+/// `tags.html` template into the `tags` directory of the output. Pages are only written if the
+/// theme provides the templates.
+pub fn write_pages(config: &Config, collection: &Collection) -> Result<()> {
+    let output = &config.toml.output;
+    let root = output.join("tags");
+    let tags = collect(collection);
+
+    if config.has_template("tag.html") {
+        for (slug, tag) in &tags {
+            let dir = root.join(slug);
+            create_dir_all(&dir)?;
+
+            let images = tag
+                .items
+                .iter()
+                .map(|item| {
+                    let parent = item.to.parent().ok_or_else(|| anyhow!("No parent"))?;
+                    Ok(Image::new(item, config)?.relative_to(&relative(parent, &dir, output)?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut context = config.context();
+            context.insert(
+                "tag",
+                &Page {
+                    name: tag.name,
+                    images,
+                },
+            );
+            context.insert(
+                "theme_url",
+                &relative(&output.join("static"), &dir, output)?,
+            );
+
+            write(
+                dir.join("index.html"),
+                config.templates.render("tag.html", &context)?,
+            )?;
+        }
+    }
+
+    if config.has_template("tags.html") {
+        create_dir_all(&root)?;
+
+        let summaries = tags
+            .iter()
+            .map(|(slug, tag)| {
+                let thumbnail = tag
+                    .items
+                    .first()
+                    .ok_or_else(|| anyhow!("Tag {} has no images", tag.name))?
+                    .thumbnail
+                    .as_path();
+
+                Ok(Summary {
+                    name: tag.name.to_owned(),
+                    path: format!("{slug}/"),
+                    count: tag.items.len(),
+                    thumbnail: relative(thumbnail, &root, output)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut context = config.context();
+        context.insert("tags", &summaries);
+        context.insert(
+            "theme_url",
+            &relative(&output.join("static"), &root, output)?,
+        );
+
+        write(
+            root.join("index.html"),
+            config.templates.render("tags.html", &context)?,
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,534 @@
+use crate::cache::Cache;
+use crate::config::{Config, Format, TOML_FILENAME};
+use crate::exclude::IGNORE_FILENAME;
+use crate::metadata::{sidecars, Metadata};
+use crate::process::{copy_recursively, detect_format};
+use crate::{
+    build, process_items, prune, write_html, write_listings, write_page, Collection, Item,
+};
+use anyhow::{anyhow, Result};
+use notify::event::{MetadataKind, ModifyKind};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Time to wait for further events before reporting changes. Editors and file managers often
+/// cause a burst of events for a single save.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Extensions of sidecar files holding additional data of source images.
+const SIDECARS: [&str; 2] = ["md", "xmp"];
+
+/// Return `true` for events that change files.
+fn is_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime)) => false,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+        _ => false,
+    }
+}
+
+/// Return the canonicalized input directory and the theme's `templates` and `static` directories
+/// of `config` that exist.
+fn watched(config: &Config) -> Result<BTreeSet<PathBuf>> {
+    let theme = &config.toml.theme.path;
+
+    [
+        config.toml.input.clone(),
+        theme.join("templates"),
+        theme.join("static"),
+    ]
+    .into_iter()
+    .filter(|path| path.exists())
+    .map(|path| Ok(path.canonicalize()?))
+    .collect()
+}
+
+/// Watch the input directory, the theme's `templates` and `static` directories and the
+/// configuration file, update `gallery` with the canonicalized paths that changed and call
+/// `on_update` afterwards. Files in the output directory are ignored. The watched directories
+/// follow changes of the configuration. Blocks forever.
+pub fn watch(
+    gallery: &mut Gallery,
+    mut on_update: impl FnMut(&Gallery) -> Result<()>,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut watching = BTreeSet::new();
+
+    let current = Path::new(".").canonicalize()?;
+
+    // Watch the directory instead of the file because editors replace it on save.
+    watcher.watch(&current, RecursiveMode::NonRecursive)?;
+
+    loop {
+        let config = gallery.config();
+        let roots = watched(config)?;
+
+        for path in watching.difference(&roots) {
+            watcher.unwatch(path)?;
+        }
+
+        for path in roots.difference(&watching) {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+        }
+
+        watching = roots;
+
+        let output = config
+            .toml
+            .output
+            .canonicalize()
+            .unwrap_or_else(|_| config.toml.output.clone());
+
+        let mut events = vec![receiver.recv()?];
+
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            events.push(event);
+        }
+
+        let mut paths = events
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|event| is_change(&event.kind))
+            .flat_map(|event| event.paths)
+            .filter(|path| !path.starts_with(&output))
+            .filter(|path| {
+                path.parent() != Some(&current)
+                    || path.file_name().is_some_and(|name| name == TOML_FILENAME)
+            })
+            .collect::<Vec<_>>();
+
+        if paths.is_empty() {
+            continue;
+        }
+
+        paths.sort();
+        paths.dedup();
+
+        if let Err(err) = gallery.update(&paths).and_then(|()| on_update(gallery)) {
+            println!("\x1B[2K\r\x1B[0;31mE\x1B[0;m {}", err);
+        }
+    }
+}
+
+/// Part of the gallery affected by a changed path.
+#[derive(Debug, PartialEq)]
+enum Change {
+    /// The configuration or the directory structure changed.
+    All,
+    /// A file of the theme's static data changed.
+    Static,
+    /// A template changed.
+    Templates,
+    /// The `index.md` of the collection at this path changed.
+    Metadata(PathBuf),
+    /// This source image was added, modified or removed.
+    Image(PathBuf),
+    /// This sidecar file of source images was added, modified or removed.
+    Sidecar(PathBuf),
+    /// This path was removed. It may have been a collection.
+    Removed(PathBuf),
+}
+
+/// Canonicalized locations watched for changes.
+struct Roots {
+    config: PathBuf,
+    templates: PathBuf,
+    static_path: PathBuf,
+    input: PathBuf,
+    /// Input directory as configured to map changed paths to collection paths.
+    configured_input: PathBuf,
+}
+
+impl Roots {
+    fn new(config: &Config) -> Result<Self> {
+        let canonical = |path: PathBuf| path.canonicalize().unwrap_or(path);
+        let theme = &config.toml.theme.path;
+
+        Ok(Self {
+            config: Path::new(".").canonicalize()?.join(TOML_FILENAME),
+            templates: canonical(theme.join("templates")),
+            static_path: canonical(theme.join("static")),
+            input: config.toml.input.canonicalize()?,
+            configured_input: config.toml.input.clone(),
+        })
+    }
+
+    /// Classify the canonicalized `path` reported by [`watch`]. Returns `None` for changes that
+    /// do not affect the output.
+    fn classify(&self, path: &Path, formats: &[Format]) -> Option<Change> {
+        if path == self.config {
+            return Some(Change::All);
+        }
+
+        if path.starts_with(&self.templates) {
+            return Some(Change::Templates);
+        }
+
+        if path.starts_with(&self.static_path) {
+            return Some(Change::Static);
+        }
+
+        let relative = path.strip_prefix(&self.input).ok()?;
+        let path = self.configured_input.join(relative);
+
+        if path.file_name().is_some_and(|name| name == IGNORE_FILENAME) {
+            return Some(Change::All);
+        }
+
+        // Dot-files and dot-directories such as .git are never part of the gallery.
+        if relative
+            .iter()
+            .any(|component| component.to_string_lossy().starts_with('.'))
+        {
+            return None;
+        }
+
+        if path.file_name().is_some_and(|name| name == "index.md") {
+            Some(Change::Metadata(path.parent()?.to_owned()))
+        } else if path.is_dir() {
+            Some(Change::All)
+        } else if detect_format(&path).is_some_and(|format| formats.contains(&format)) {
+            Some(Change::Image(path))
+        } else if path
+            .extension()
+            .is_some_and(|extension| SIDECARS.iter().any(|sidecar| extension == *sidecar))
+        {
+            Some(Change::Sidecar(path))
+        } else if !path.exists() {
+            Some(Change::Removed(path))
+        } else {
+            None
+        }
+    }
+}
+
+/// Built gallery kept in memory to only rebuild the parts affected by changes.
+pub struct Gallery {
+    config: Config,
+    collection: Collection,
+}
+
+impl Gallery {
+    /// Build the gallery described by `config`.
+    pub fn new(config: Config) -> Result<Self> {
+        let collection = build(&config)?;
+        Ok(Self { config, collection })
+    }
+
+    /// Return the configuration the gallery was last built with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Rebuild the parts of the gallery affected by the canonicalized `paths`. Changed images
+    /// are processed and their collection pages are written, changed `index.md` files only cause
+    /// their collection pages to be written and changed templates cause all pages but no images
+    /// to be written. Everything is rebuilt if the configuration or directories changed.
+    pub fn update(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let roots = Roots::new(&self.config)?;
+        let changes = paths
+            .iter()
+            .filter_map(|path| roots.classify(path, &self.config.toml.formats))
+            .collect::<Vec<_>>();
+
+        if changes.iter().any(|change| self.restructures(change)) {
+            self.config = Config::read()?;
+            self.collection = build(&self.config)?;
+            return Ok(());
+        }
+
+        let mut pages = BTreeSet::new();
+        let mut images = Vec::new();
+        let mut templates = false;
+
+        for change in changes {
+            match change {
+                Change::Static => {
+                    if let Some(static_path) = self.config.static_path.as_ref() {
+                        copy_recursively(static_path, &self.config.toml.output)?;
+                        println!("\x1B[2K\r\x1B[0;32m✔\x1B[0;m Copied static data");
+                    }
+                }
+                Change::Templates => templates = true,
+                Change::Metadata(path) => {
+                    if let Some(collection) = self.collection.find_mut(&path) {
+                        collection.metadata = Metadata::from_path(&path)?;
+                        pages.insert(path);
+                    }
+                }
+                Change::Image(path) => images.push(path),
+                Change::Sidecar(path) => images.extend(self.sidecar_images(&path)),
+                Change::All | Change::Removed(_) => {}
+            }
+        }
+
+        if !images.is_empty() && !self.update_images(&images)? {
+            self.collection = build(&self.config)?;
+            return Ok(());
+        }
+
+        pages.extend(
+            images
+                .iter()
+                .filter_map(|path| path.parent())
+                .map(Path::to_owned),
+        );
+
+        self.collection.update_thumbnails()?;
+
+        if templates {
+            self.config.templates.full_reload()?;
+
+            let mut breadcrumbs = vec![String::from("home")];
+            write_html(
+                &self.config,
+                &self.collection,
+                &mut breadcrumbs,
+                &self.config.toml.output,
+            )?;
+            write_listings(&self.config, &self.collection)?;
+            prune::record(&self.config, &self.collection)?;
+            println!("\x1B[2K\r\x1B[0;32m✔\x1B[0;m Wrote HTML pages");
+            return Ok(());
+        }
+
+        // Titles and thumbnails are shown on the pages of all parent collections.
+        let pages = pages
+            .iter()
+            .flat_map(|path| path.ancestors())
+            .filter(|path| path.starts_with(&self.config.toml.input))
+            .collect::<BTreeSet<_>>();
+
+        for path in &pages {
+            self.write_page(path)?;
+        }
+
+        if !pages.is_empty() {
+            // Changed images or metadata may change any tag or archive page.
+            write_listings(&self.config, &self.collection)?;
+            prune::record(&self.config, &self.collection)?;
+
+            println!(
+                "\x1B[2K\r\x1B[0;32m✔\x1B[0;m Wrote {} HTML pages",
+                pages.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Return `true` if `change` adds or removes collections.
+    fn restructures(&self, change: &Change) -> bool {
+        match change {
+            Change::All => true,
+            Change::Removed(path) => self.collection.find(path).is_some(),
+            Change::Image(path) => path
+                .parent()
+                .is_none_or(|parent| self.collection.find(parent).is_none()),
+            // A different minimum rating, different tags or settings change the items, the flags
+            // change the listings and the sort order is inherited by sub collections. Unknown
+            // collections may have been drafts.
+            Change::Metadata(path) => self.collection.find(path).is_none_or(|collection| {
+                Metadata::from_path(path).map_or(true, |metadata| {
+                    metadata.min_rating != collection.metadata.min_rating
+                        || metadata.tags != collection.metadata.tags
+                        || metadata.draft != collection.metadata.draft
+                        || metadata.hidden != collection.metadata.hidden
+                        || metadata.settings != collection.metadata.settings
+                        || metadata.sort != collection.metadata.sort
+                        || metadata.sort_children != collection.metadata.sort_children
+                })
+            }),
+            _ => false,
+        }
+    }
+
+    /// Return the source images described by the sidecar file at `path`.
+    fn sidecar_images(&self, path: &Path) -> Vec<PathBuf> {
+        let Some(collection) = path
+            .parent()
+            .and_then(|parent| self.collection.find(parent))
+        else {
+            return vec![];
+        };
+
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+
+        collection
+            .items
+            .iter()
+            .filter(|item| sidecars(&item.from, &extension).iter().any(|p| p == path))
+            .map(|item| item.from.clone())
+            .collect()
+    }
+
+    /// Replace the items of the changed source images at `paths` and process them. Returns
+    /// `false` if a collection became empty and the gallery must be rebuilt.
+    fn update_images(&mut self, paths: &[PathBuf]) -> Result<bool> {
+        let mut items = Vec::new();
+
+        for path in paths {
+            let parent = path.parent().ok_or_else(|| anyhow!("No parent"))?;
+            let collection = self
+                .collection
+                .find_mut(parent)
+                .ok_or_else(|| anyhow!("No collection for {:?}", path))?;
+
+            collection.items.retain(|item| &item.from != path);
+
+            if let Some(format) = detect_format(path)
+                .filter(|_| path.exists() && !collection.exclude.is_excluded(&self.config, path))
+            {
+                let mut item = Item::new(
+                    path.to_owned(),
+                    format,
+                    &self.config,
+                    collection.settings.clone(),
+                )?;
+                item.add_tags(&collection.metadata.tags);
+
+                if item.is_published(collection.settings.min_rating) {
+                    items.push(item);
+                }
+            }
+        }
+
+        let output = &self.config.toml.output;
+        let mut cache = Cache::load(output);
+        let updated = process_items(&self.config, &items.iter().collect::<Vec<_>>(), &cache)?;
+        cache.extend(updated);
+        cache.save(output)?;
+
+        for item in items {
+            let parent = item.from.parent().ok_or_else(|| anyhow!("No parent"))?;
+
+            if let Some(collection) = self.collection.find_mut(parent) {
+                collection.items.push(item);
+            }
+        }
+
+        let emptied = paths.iter().filter_map(|path| path.parent()).any(|parent| {
+            self.collection.find(parent).is_none_or(|collection| {
+                collection.items.is_empty() && collection.collections.is_empty()
+            })
+        });
+
+        Ok(!emptied)
+    }
+
+    /// Write the page of the collection at `path`.
+    fn write_page(&self, path: &Path) -> Result<()> {
+        let Some(collection) = self.collection.find(path) else {
+            return Ok(());
+        };
+
+        let relative = path.strip_prefix(&self.config.toml.input)?;
+        let breadcrumbs = iter::once(String::from("home"))
+            .chain(
+                relative
+                    .iter()
+                    .map(|component| component.to_string_lossy().to_string()),
+            )
+            .collect::<Vec<_>>();
+
+        write_page(
+            &self.config,
+            collection,
+            &breadcrumbs,
+            &self.config.toml.output.join(relative),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::tests::METADATA;
+    use crate::tests::setup;
+    use std::fs::{copy, create_dir, read_to_string, write};
+
+    #[test]
+    fn classify_paths() -> Result<()> {
+        let f = setup(None)?;
+        let input = &f.config.toml.input;
+        create_dir(input.join("a"))?;
+        copy("data/test.jpg", input.join("a/test.jpg"))?;
+        write(input.join("a/notes.txt"), "")?;
+
+        let roots = Roots::new(&f.config)?;
+        let classify = |path: &Path| roots.classify(&roots.input.join(path), &[Format::Jpeg]);
+
+        assert_eq!(
+            classify(Path::new("a/index.md")),
+            Some(Change::Metadata(input.join("a")))
+        );
+        assert_eq!(
+            classify(Path::new("a/test.jpg")),
+            Some(Change::Image(input.join("a/test.jpg")))
+        );
+        assert_eq!(
+            classify(Path::new("a/removed.jpg")),
+            Some(Change::Image(input.join("a/removed.jpg")))
+        );
+        assert_eq!(
+            classify(Path::new("b")),
+            Some(Change::Removed(input.join("b")))
+        );
+        assert_eq!(classify(Path::new("a")), Some(Change::All));
+        assert_eq!(
+            classify(Path::new("a/test.md")),
+            Some(Change::Sidecar(input.join("a/test.md")))
+        );
+        assert_eq!(classify(Path::new("a/notes.txt")), None);
+        assert_eq!(classify(Path::new(".git/index")), None);
+        assert_eq!(classify(Path::new("a/.splatignore")), Some(Change::All));
+        assert_eq!(
+            roots.classify(&roots.templates.join("index.html"), &[]),
+            Some(Change::Templates)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn update_changed_parts() -> Result<()> {
+        let mut f = setup(None)?;
+        let input = f.config.toml.input.canonicalize()?;
+        let output = f.config.toml.output.clone();
+        let template = f.config.toml.theme.path.join("templates/index.html");
+        create_dir(input.join("a"))?;
+        copy("data/test.jpg", input.join("a/one.jpg"))?;
+
+        write(
+            &template,
+            "{{ collection.title }}{% for image in collection.images %} {{ image.path }}{% endfor %}",
+        )?;
+        f.config.templates.full_reload()?;
+
+        let mut gallery = Gallery::new(f.config)?;
+        let thumbnail = output.join("a/thumbnails/one.jpg");
+        let modified = thumbnail.metadata()?.modified()?;
+
+        copy("data/test.jpg", input.join("a/two.jpg"))?;
+        write(input.join("a/index.md"), METADATA)?;
+        gallery.update(&[input.join("a/index.md"), input.join("a/two.jpg")])?;
+
+        assert!(output.join("a/thumbnails/two.jpg").exists());
+        assert_eq!(thumbnail.metadata()?.modified()?, modified);
+        assert_eq!(
+            read_to_string(output.join("a/index.html"))?,
+            "foo one.jpg two.jpg"
+        );
+
+        write(&template, "{{ collection.title }}")?;
+        gallery.update(&[template.canonicalize()?])?;
+
+        assert_eq!(read_to_string(output.join("a/index.html"))?, "foo");
+        assert_eq!(thumbnail.metadata()?.modified()?, modified);
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+use crate::metadata::sidecars;
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::NsReader;
+use serde::Serialize;
+use std::fs::{read_to_string, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+const DC: &[u8] = b"http://purl.org/dc/elements/1.1/";
+const XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/";
+const RDF: &[u8] = b"http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// Header of the APP1 segment that embeds an XMP packet in a JPEG.
+const APP1_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Photo details read from XMP as written by darktable or Lightroom. Properties that are not
+/// present are left out.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Xmp {
+    /// `dc:title` of the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// `dc:description` of the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// `dc:subject` keywords.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// `xmp:Rating` from -1 (rejected) to 5 stars.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<i8>,
+}
+
+/// XMP property read by [`Xmp`].
+#[derive(Clone, Copy)]
+enum Property {
+    Title,
+    Description,
+    Subject,
+    Rating,
+}
+
+impl Property {
+    fn from_name(namespace: &ResolveResult, name: &[u8]) -> Option<Self> {
+        match (namespace, name) {
+            (ResolveResult::Bound(Namespace(DC)), b"title") => Some(Self::Title),
+            (ResolveResult::Bound(Namespace(DC)), b"description") => Some(Self::Description),
+            (ResolveResult::Bound(Namespace(DC)), b"subject") => Some(Self::Subject),
+            (ResolveResult::Bound(Namespace(XMP)), b"Rating") => Some(Self::Rating),
+            _ => None,
+        }
+    }
+}
+
+fn is_rdf(namespace: &ResolveResult, name: &[u8], expected: &[u8]) -> bool {
+    matches!(namespace, ResolveResult::Bound(Namespace(RDF))) && name == expected
+}
+
+impl Xmp {
+    fn set(&mut self, property: Property, value: &str) {
+        let value = value.trim();
+
+        if value.is_empty() {
+            return;
+        }
+
+        match property {
+            Property::Title => {
+                self.title.get_or_insert_with(|| value.to_owned());
+            }
+            Property::Description => {
+                self.description.get_or_insert_with(|| value.to_owned());
+            }
+            Property::Subject => self.keywords.push(value.to_owned()),
+            Property::Rating => self.rating = value.parse().ok(),
+        }
+    }
+
+    /// Set properties given as attributes of an `rdf:Description` element.
+    fn set_attributes(&mut self, reader: &NsReader<&[u8]>, element: &BytesStart) -> Result<()> {
+        for attribute in element.attributes() {
+            let attribute = attribute?;
+            let (namespace, name) = reader.resolve_attribute(attribute.key);
+
+            if let Some(property) = Property::from_name(&namespace, name.as_ref()) {
+                self.set(property, &attribute.unescape_value()?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse an XMP packet. Properties may be given as attributes of `rdf:Description` or as
+    /// elements with plain, `rdf:Alt` or `rdf:Bag` values. Of alternatives the first is used.
+    pub fn from_str(xml: &str) -> Result<Self> {
+        let mut reader = NsReader::from_str(xml);
+        let mut xmp = Self::default();
+        let mut property = None;
+
+        loop {
+            match reader.read_resolved_event()? {
+                (namespace, Event::Start(element)) => {
+                    let name = element.local_name();
+
+                    if is_rdf(&namespace, name.as_ref(), b"Description") {
+                        xmp.set_attributes(&reader, &element)?;
+                    } else if property.is_none() {
+                        property = Property::from_name(&namespace, name.as_ref());
+                    }
+                }
+                (namespace, Event::Empty(element))
+                    if is_rdf(&namespace, element.local_name().as_ref(), b"Description") =>
+                {
+                    xmp.set_attributes(&reader, &element)?;
+                }
+                (namespace, Event::End(element))
+                    if Property::from_name(&namespace, element.local_name().as_ref()).is_some() =>
+                {
+                    property = None;
+                }
+                (_, Event::Text(text)) => {
+                    if let Some(property) = property {
+                        xmp.set(property, &text.unescape()?);
+                    }
+                }
+                (_, Event::Eof) => break,
+                _ => {}
+            }
+        }
+
+        Ok(xmp)
+    }
+
+    /// Read XMP from the first `.xmp` sidecar file of `path` and the XMP packet embedded in
+    /// `path` if it is a JPEG. Properties of the sidecar take precedence. Missing or broken XMP
+    /// is not an error but results in an empty [`Xmp`].
+    pub fn from_path(path: &Path) -> Self {
+        let sidecar = sidecars(path, "xmp")
+            .into_iter()
+            .find(|sidecar| sidecar.exists())
+            .and_then(|sidecar| read_to_string(sidecar).ok())
+            .and_then(|xml| Self::from_str(&xml).ok())
+            .unwrap_or_default();
+
+        let embedded = embedded(path)
+            .ok()
+            .flatten()
+            .and_then(|xml| Self::from_str(&xml).ok())
+            .unwrap_or_default();
+
+        Self {
+            title: sidecar.title.or(embedded.title),
+            description: sidecar.description.or(embedded.description),
+            keywords: if sidecar.keywords.is_empty() {
+                embedded.keywords
+            } else {
+                sidecar.keywords
+            },
+            rating: sidecar.rating.or(embedded.rating),
+        }
+    }
+}
+
+/// Return the XMP packet embedded in the APP1 segment of the JPEG at `path`, if any.
+fn embedded(path: &Path) -> Result<Option<String>> {
+    Ok(segment(path, 0xe1, APP1_HEADER)?
+        .map(|packet| String::from_utf8_lossy(&packet).into_owned()))
+}
+
+/// Return the content following `header` of the first segment with `marker` in the JPEG at
+/// `path`. Returns `None` for other file types.
+pub fn segment(path: &Path, marker: u8, header: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut current = [0u8; 2];
+    reader.read_exact(&mut current)?;
+
+    if current != [0xff, 0xd8] {
+        return Ok(None);
+    }
+
+    loop {
+        reader.read_exact(&mut current)?;
+
+        // Stop at the start of the image data or anything that is not a marker.
+        if current[0] != 0xff || current[1] == 0xda || current[1] == 0xd9 {
+            return Ok(None);
+        }
+
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let length = usize::from(u16::from_be_bytes(length)).saturating_sub(2);
+        let mut segment = vec![0u8; length];
+        reader.read_exact(&mut segment)?;
+
+        if current[1] == marker && segment.starts_with(header) {
+            return Ok(Some(segment.split_off(header.len())));
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::fs::{read, write};
+    use tempfile::tempdir;
+
+    /// Insert an APP1 segment containing the XMP packet `xml` right after the SOI marker of
+    /// `jpeg`.
+    pub fn with_xmp(jpeg: &[u8], xml: &str) -> Result<Vec<u8>> {
+        let mut result = jpeg[..2].to_vec();
+        result.extend_from_slice(&[0xff, 0xe1]);
+        result.extend_from_slice(&u16::try_from(xml.len() + APP1_HEADER.len() + 2)?.to_be_bytes());
+        result.extend_from_slice(APP1_HEADER);
+        result.extend_from_slice(xml.as_bytes());
+        result.extend_from_slice(&jpeg[2..]);
+        Ok(result)
+    }
+
+    /// Return an XMP packet with `rating` and `keywords`.
+    pub fn xmp(rating: i8, keywords: &[&str]) -> String {
+        let keywords = keywords
+            .iter()
+            .map(|keyword| format!("<rdf:li>{keyword}</rdf:li>"))
+            .collect::<String>();
+
+        format!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+      xmlns:dc="http://purl.org/dc/elements/1.1/" xmp:Rating="{rating}">
+   <dc:subject><rdf:Bag>{keywords}</rdf:Bag></dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#
+        )
+    }
+
+    #[test]
+    fn parse_xmp() -> Result<()> {
+        let xml = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:d="http://purl.org/dc/elements/1.1/"
+      xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+   <xmp:Rating>4</xmp:Rating>
+   <d:title><rdf:Alt><rdf:li xml:lang="x-default">Harbour &amp; boats</rdf:li></rdf:Alt></d:title>
+   <d:description><rdf:Alt><rdf:li xml:lang="x-default">At dawn</rdf:li></rdf:Alt></d:description>
+   <d:subject><rdf:Bag><rdf:li>sea</rdf:li><rdf:li>boat</rdf:li></rdf:Bag></d:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+        assert_eq!(
+            Xmp::from_str(xml)?,
+            Xmp {
+                title: Some("Harbour & boats".to_owned()),
+                description: Some("At dawn".to_owned()),
+                keywords: vec!["sea".to_owned(), "boat".to_owned()],
+                rating: Some(4),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sidecar_overrides_embedded() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("xmp.jpg");
+        write(
+            &path,
+            with_xmp(&read("data/test.jpg")?, &xmp(2, &["embedded"]))?,
+        )?;
+
+        assert_eq!(
+            Xmp::from_path(&path),
+            Xmp {
+                keywords: vec!["embedded".to_owned()],
+                rating: Some(2),
+                ..Xmp::default()
+            }
+        );
+
+        write(dir.path().join("xmp.xmp"), xmp(5, &[]))?;
+
+        assert_eq!(
+            Xmp::from_path(&path),
+            Xmp {
+                keywords: vec!["embedded".to_owned()],
+                rating: Some(5),
+                ..Xmp::default()
+            }
+        );
+        Ok(())
+    }
+}